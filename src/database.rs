@@ -1,14 +1,25 @@
+mod catalog;
+mod digest;
 mod getters;
 mod insert_clients;
 mod insert_songs;
 mod insert_videos;
+mod keys;
+mod local_files;
+mod peer_files;
+mod quarantine;
+mod schema;
+mod search;
 
 use serde::{Deserialize, Serialize};
 use sled::{self, Tree};
-use std::{collections::HashSet, fs, process};
+use std::{collections::HashSet, fs};
 use wg_internal::network::NodeId;
 
+use crate::error::DatabaseError;
 use packet_forge::{ClientType, FileHash, Metadata};
+use schema::SCHEMA_VERSION;
+pub(crate) use search::MAX_PAGE_SIZE;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientInfo {
@@ -20,105 +31,201 @@ pub struct ClientInfo {
 pub struct FileEntry<T> {
     pub file_metadata: T,
     pub peers: HashSet<NodeId>, // List of clients sharing the file
+    pub digest: u64, // Full-width digest of the metadata, tells apart files with the same FileHash
+}
+
+/// Tree a shared file belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileKind {
+    Song = 0,
+    Video = 1,
+}
+
+impl From<&ClientType> for FileKind {
+    fn from(client_type: &ClientType) -> Self {
+        match client_type {
+            ClientType::Song => FileKind::Song,
+            ClientType::Video => FileKind::Video,
+        }
+    }
+}
+
+/// Size of a stored video chunk (64 KiB)
+pub(crate) const VIDEO_CHUNK_SIZE: usize = 256 * 256;
+
+/// Layout of a video payload stored as `pl{n}` chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoChunksInfo {
+    pub n_chunks: u32,
+    pub chunk_size: u32,
+    pub total_size: u64,
+    pub checksums: Vec<u32>, // Adler-32 of each chunk
+}
+
+/// How `Database::init` treats the data found in an existing database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CatalogMode {
+    /// Wipe every tree and ingest all the local files
+    #[default]
+    Fresh,
+    /// Keep clients and shared files, ingest only the local files that are new or changed
+    Persistent,
+}
+
+/// Where the `Database` keeps its data
+#[derive(Debug, Clone)]
+pub enum DatabaseLocation {
+    /// On disk, at the given path
+    Path(String),
+    /// In a temporary sled database, removed when dropped (useful for tests)
+    Temporary,
 }
 
 pub struct Database {
     db: sled::Db,
-    video_tree: Tree,
-    songs_tree: Tree,
+    video_tree: Tree,          // FileHash -> FileEntry<VideoMetaData>
+    songs_tree: Tree,          // FileHash -> FileEntry<SongMetaData>
+    video_payloads_tree: Tree, // PayloadKey -> chunk or VideoChunksInfo
+    song_payloads_tree: Tree,  // PayloadKey -> segment
     clients_tree: Tree,
+    local_files_tree: Tree,
+    peer_files_tree: Tree, // (peer, kind, file) -> (), files shared by each peer
+    search_index_tree: Tree, // (kind, field, value, file) -> (), secondary indexes of the metadata
+    catalog_tree: Tree,    // last change of each file and change log, see `catalog`
+    quarantine_tree: Tree, // (client, kind, file) -> FileMetadata, submitted files that failed validation
+    digest_keys_tree: Tree, // (kind, digest) -> FileHash, key of each file, see `digest`
     server_id: NodeId,
 }
 
-/// Build a key: prefix:id
-pub(crate) fn construct_payload_key(prefix: &str, id: u16) -> Vec<u8> {
-    let key = format!("{prefix}:{id}").as_bytes().to_vec();
-    key
+/// Adler-32 checksum of `data`
+pub(crate) fn checksum(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
 }
 
 impl Database {
-    /// Creates or opens a database at the specified path.
-    pub fn new(database: &str, server_id: NodeId) -> Self {
-        let db = sled::open(database).unwrap_or_else(|e| {
-            eprintln!("Error opening database: {e}");
-            process::exit(1); // Exit the program with an error code
-        });
-
-        // Helper function to open trees and exit on error
-        let open_tree = |tree_name: &str| {
-            db.open_tree(tree_name).unwrap_or_else(|e| {
-                eprintln!("Error opening {tree_name} tree: {e}");
-                process::exit(1); // Exit the program with an error code
-            })
+    /// Creates or opens a database at the specified location.
+    /// An existing database is migrated to the current schema version by `init`, and only if its data is kept.
+    /// ### Error
+    /// Returns `DatabaseError::Sled` if the database or one of its trees cannot be opened.
+    pub fn open(location: &DatabaseLocation, server_id: NodeId) -> Result<Self, DatabaseError> {
+        let db = match location {
+            DatabaseLocation::Path(path) => sled::open(path)?,
+            DatabaseLocation::Temporary => sled::Config::new().temporary(true).open()?,
         };
 
-        let video_tree = open_tree("video");
-        let songs_tree = open_tree("songs");
-        let clients_tree = open_tree("clients");
-
-        Database {
+        let video_tree = db.open_tree("video")?;
+        let songs_tree = db.open_tree("songs")?;
+        let video_payloads_tree = db.open_tree("video_payloads")?;
+        let song_payloads_tree = db.open_tree("song_payloads")?;
+        let clients_tree = db.open_tree("clients")?;
+        let local_files_tree = db.open_tree("local_files")?;
+        let peer_files_tree = db.open_tree("peer_files")?;
+        let search_index_tree = db.open_tree("search_index")?;
+        let catalog_tree = db.open_tree("catalog")?;
+        let quarantine_tree = db.open_tree("quarantine")?;
+        let digest_keys_tree = db.open_tree("digest_keys")?;
+
+        let database = Database {
             db,
             video_tree,
             songs_tree,
+            video_payloads_tree,
+            song_payloads_tree,
             clients_tree,
+            local_files_tree,
+            peer_files_tree,
+            search_index_tree,
+            catalog_tree,
+            quarantine_tree,
+            digest_keys_tree,
             server_id,
+        };
+        // A new database starts at the current layout, there is nothing to migrate
+        if !database.db.was_recovered() {
+            database.set_schema_version(SCHEMA_VERSION)?;
         }
+        Ok(database)
     }
 
-    fn clear_database(&self) -> Result<(), String> {
+    /// Remove every entry. The database gets the current schema version and keeps the catalog version,
+    /// unless it cannot be read (e.g. it was written by a newer server).
+    fn clear_database(&self) -> Result<(), DatabaseError> {
+        let catalog_version = self.get_catalog_version().unwrap_or(0);
+
         let trees = [
             &self.db,
             &self.video_tree,
             &self.songs_tree,
+            &self.video_payloads_tree,
+            &self.song_payloads_tree,
             &self.clients_tree,
+            &self.local_files_tree,
+            &self.peer_files_tree,
+            &self.search_index_tree,
+            &self.catalog_tree,
+            &self.quarantine_tree,
+            &self.digest_keys_tree,
         ];
 
         for tree in trees {
-            tree.clear()
-                .map_err(|e| format!("Error clearing database: {e}"))?;
-            tree.flush()
-                .map_err(|e| format!("Error flushing database: {e}"))?;
+            tree.clear()?;
+            tree.flush()?;
         }
 
+        // The default tree holds the schema and catalog versions
+        self.set_schema_version(SCHEMA_VERSION)?;
+        self.set_catalog_version(catalog_version)?;
         Ok(())
     }
 
     fn load_json_metadata<T: Metadata>(
         json_file_path: &str,
         json_array: &str,
-    ) -> Result<Vec<T>, String> {
-        let file_content = fs::read_to_string(json_file_path)
-            .map_err(|e| format!("Error reading file {json_file_path}: {e}"))?;
+    ) -> Result<Vec<T>, DatabaseError> {
+        let file_content =
+            fs::read_to_string(json_file_path).map_err(|source| DatabaseError::Io {
+                path: json_file_path.to_string(),
+                source,
+            })?;
 
-        let json_data: serde_json::Value =
-            serde_json::from_str(&file_content).map_err(|e| format!("Error parsing JSON: {e}"))?;
+        let json_data: serde_json::Value = serde_json::from_str(&file_content)?;
 
         let data_array = json_data[json_array]
             .as_array()
-            .ok_or_else(|| format!("Invalid JSON: '{json_array}' is not an array"))?;
+            .ok_or_else(|| DatabaseError::InvalidJson(format!("'{json_array}' is not an array")))?;
 
         data_array
             .iter()
-            .map(|song| {
-                serde_json::from_value(song.clone()).map_err(|e| format!("Invalid song data: {e}"))
-            })
+            .map(|entry| serde_json::from_value(entry.clone()).map_err(DatabaseError::from))
             .collect()
     }
 
     /// Initializes the database:
-    /// - clears existing entries
-    /// - checks for data from local files (songs and video).
+    /// - `CatalogMode::Fresh` clears existing entries whatever their schema version,
+    ///   `CatalogMode::Persistent` keeps them and migrates them to the current schema version
+    /// - checks for data from local files (songs and video), skipping the ones unchanged since the last ingest.
     /// ### Arguments
     /// - `local_path`: the folder containing the two JSON files
     /// - `file_songs_name`: the name of the file with the song array. It must contain the extension (*.json)
     /// - `file_video_name`: the name of the file with the video array. It must contain the extension (*.json)
+    /// - `mode`: whether the existing data is kept
     pub fn init(
         &self,
         local_path: &str,
         file_songs_name: Option<&str>,
         file_video_name: Option<&str>,
-    ) -> Result<(), String> {
-        self.clear_database()?;
+        mode: CatalogMode,
+    ) -> Result<(), DatabaseError> {
+        match mode {
+            CatalogMode::Fresh => self.clear_database()?,
+            CatalogMode::Persistent => self.migrate()?,
+        }
 
         if let Some(file_name) = file_songs_name {
             let songs_metadata_path = local_path.to_string() + "/" + file_name;
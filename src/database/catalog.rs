@@ -0,0 +1,258 @@
+use packet_forge::{FileHash, FileMetadata, FileStatus};
+use std::ops::Bound;
+use wg_internal::network::NodeId;
+
+use super::schema::{decode, encode};
+use super::{Database, FileKind};
+use crate::error::DatabaseError;
+
+/// Key of the catalog version in the default tree
+const CATALOG_VERSION_KEY: &[u8] = b"catalog_version";
+
+/// Key family of the `catalog_tree` mapping each file to the version of its last change
+const LAST_CHANGE: u8 = 0;
+/// Key family of the `catalog_tree` listing the changes ordered by version
+const CHANGE_LOG: u8 = 1;
+
+/// `[LAST_CHANGE][kind][file BE]` -> version of the last change of the file
+fn last_change_key(kind: FileKind, file: FileHash) -> Vec<u8> {
+    let mut key = vec![LAST_CHANGE, kind as u8];
+    key.extend_from_slice(&file.to_be_bytes());
+    key
+}
+
+/// `[CHANGE_LOG][kind][version BE][file BE]` -> metadata of the file if it was removed, empty otherwise
+fn change_log_key(kind: FileKind, version: u64, file: FileHash) -> Vec<u8> {
+    let mut key = vec![CHANGE_LOG, kind as u8];
+    key.extend_from_slice(&version.to_be_bytes());
+    key.extend_from_slice(&file.to_be_bytes());
+    key
+}
+
+/// Decode the version and the file of a `change_log_key`
+fn decode_change_log_key(key: &[u8]) -> Option<(u64, FileHash)> {
+    let version = u64::from_be_bytes(key.get(2..10)?.try_into().ok()?);
+    let file = FileHash::from_be_bytes(key.get(10..12)?.try_into().ok()?);
+    Some((version, file))
+}
+
+/// Changes of the catalog after a given version
+#[derive(Debug)]
+pub(crate) struct CatalogChanges {
+    pub changes: Vec<(FileMetadata, FileStatus)>,
+    /// Version to ask the following changes from
+    pub version: u64,
+}
+
+impl Database {
+    /// Returns the version of the catalog, increased on every change of a file entry.
+    pub(crate) fn get_catalog_version(&self) -> Result<u64, DatabaseError> {
+        match self.db.get(CATALOG_VERSION_KEY)? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Overwrite the catalog version, it must never decrease.
+    pub(crate) fn set_catalog_version(&self, version: u64) -> Result<(), DatabaseError> {
+        self.db
+            .insert(CATALOG_VERSION_KEY, bincode::serialize(&version)?)?;
+        Ok(())
+    }
+
+    /// Increase the catalog version and record that the file changed.
+    /// `removed` holds the metadata of the file if its entry has been removed.
+    /// Only the last change of each file is kept.
+    pub(crate) fn record_catalog_change(
+        &self,
+        kind: FileKind,
+        file: FileHash,
+        removed: Option<&FileMetadata>,
+    ) -> Result<(), DatabaseError> {
+        let version = self
+            .db
+            .update_and_fetch(CATALOG_VERSION_KEY, |current| {
+                let current: u64 = current
+                    .and_then(|data| bincode::deserialize(data).ok())
+                    .unwrap_or(0);
+                bincode::serialize(&(current + 1)).ok()
+            })?
+            .map_or(Ok(0), |data| bincode::deserialize(&data))?;
+
+        let last_change = last_change_key(kind, file);
+        if let Some(data) = self.catalog_tree.get(&last_change)? {
+            let previous: u64 = decode(&data)?;
+            self.catalog_tree
+                .remove(change_log_key(kind, previous, file))?;
+        }
+
+        let tombstone = match removed {
+            Some(metadata) => encode(metadata)?,
+            None => Vec::new(),
+        };
+        self.catalog_tree
+            .insert(change_log_key(kind, version, file), tombstone)?;
+        self.catalog_tree.insert(last_change, encode(&version)?)?;
+        Ok(())
+    }
+
+    /// Returns at most `limit` changes of the files of type `kind` after version `since`.
+    /// Files shared by `requester` are skipped, as they are not part of its file list.
+    /// A `limit` of 0 returns one change, so that the returned version always moves past `since`.
+    pub(crate) fn get_catalog_changes(
+        &self,
+        kind: FileKind,
+        since: u64,
+        requester: NodeId,
+        limit: usize,
+    ) -> Result<CatalogChanges, DatabaseError> {
+        let limit = limit.max(1);
+
+        // Read first: changes recorded while scanning are returned by the next request
+        let current_version = self.get_catalog_version()?;
+        if since >= current_version {
+            return Ok(CatalogChanges {
+                changes: Vec::new(),
+                version: current_version,
+            });
+        }
+
+        let start = change_log_key(kind, since, FileHash::MAX);
+        let end = change_log_key(kind, current_version, FileHash::MAX);
+
+        let mut changes = Vec::new();
+        for entry in self
+            .catalog_tree
+            .range((Bound::Excluded(start), Bound::Included(end)))
+        {
+            let (key, data) = entry?;
+            let Some((version, file)) = decode_change_log_key(&key) else {
+                continue;
+            };
+
+            if changes.len() == limit {
+                return Ok(CatalogChanges {
+                    changes,
+                    version: version - 1,
+                });
+            }
+
+            if !data.is_empty() {
+                changes.push((decode(&data)?, FileStatus::Deleted));
+                continue;
+            }
+
+            let entry = match kind {
+                FileKind::Song => self
+                    .get_song_entry(file)
+                    .map(|entry| (FileMetadata::Song(entry.file_metadata), entry.peers)),
+                FileKind::Video => self
+                    .get_video_entry(file)
+                    .map(|entry| (FileMetadata::Video(entry.file_metadata), entry.peers)),
+            };
+            match entry {
+                Ok((metadata, peers)) if !peers.contains(&requester) => {
+                    changes.push((metadata, FileStatus::New));
+                }
+                Ok(_) | Err(DatabaseError::SongNotFound(_) | DatabaseError::VideoNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(CatalogChanges {
+            changes,
+            version: current_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseLocation;
+    use crate::test_fixtures::song;
+
+    #[test]
+    fn returns_changes_after_version() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database.insert_song_peer(&song(10, "first"), 2).unwrap();
+        let since = database.get_catalog_version().unwrap();
+        database.insert_song_peer(&song(20, "second"), 2).unwrap();
+
+        let changes = database
+            .get_catalog_changes(FileKind::Song, since, 3, 10)
+            .unwrap();
+        assert_eq!(changes.version, since + 1);
+        assert_eq!(changes.changes.len(), 1);
+        assert!(matches!(
+            &changes.changes[0],
+            (FileMetadata::Song(song), FileStatus::New) if song.id == 20
+        ));
+
+        // The files shared by the requester are not part of its changes
+        let own = database
+            .get_catalog_changes(FileKind::Song, 0, 2, 10)
+            .unwrap();
+        assert!(own.changes.is_empty());
+    }
+
+    #[test]
+    fn pages_changes_by_limit() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        // Files with the same metadata share one key
+        for id in 1..=3 {
+            database
+                .insert_song_peer(&song(id, &format!("title {id}")), 2)
+                .unwrap();
+        }
+
+        let first = database
+            .get_catalog_changes(FileKind::Song, 0, 3, 2)
+            .unwrap();
+        assert_eq!(first.changes.len(), 2);
+        let rest = database
+            .get_catalog_changes(FileKind::Song, first.version, 3, 2)
+            .unwrap();
+        assert_eq!(rest.changes.len(), 1);
+        assert_eq!(rest.version, database.get_catalog_version().unwrap());
+    }
+
+    #[test]
+    fn zero_limit_still_progresses() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database.insert_song_peer(&song(10, "first"), 2).unwrap();
+        database.insert_song_peer(&song(20, "second"), 2).unwrap();
+
+        let changes = database
+            .get_catalog_changes(FileKind::Song, 0, 3, 0)
+            .unwrap();
+        assert_eq!(changes.changes.len(), 1);
+        assert_eq!(changes.version, 1);
+    }
+
+    #[test]
+    fn new_peer_is_not_a_change() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database.insert_song_peer(&song(10, "title"), 2).unwrap();
+        let version = database.get_catalog_version().unwrap();
+
+        database.insert_song_peer(&song(10, "title"), 3).unwrap();
+        assert_eq!(database.get_catalog_version().unwrap(), version);
+    }
+
+    #[test]
+    fn removed_file_is_reported_deleted() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database.insert_song_peer(&song(10, "title"), 2).unwrap();
+        let since = database.get_catalog_version().unwrap();
+        database.remove_song_peer(10, 2).unwrap();
+
+        let changes = database
+            .get_catalog_changes(FileKind::Song, since, 3, 10)
+            .unwrap();
+        assert!(matches!(
+            &changes.changes[..],
+            [(FileMetadata::Song(song), FileStatus::Deleted)] if song.id == 10
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+use packet_forge::{FileHash, SongMetaData, VideoMetaData};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::Tree;
+use std::collections::HashSet;
+use wg_internal::network::NodeId;
+
+use super::keys::{decode_entry_key, entry_key};
+use super::local_files::{fnv1a64, FNV_OFFSET_BASIS};
+use super::schema::decode;
+use super::search::Searchable;
+use super::{Database, FileEntry, FileKind};
+use crate::error::DatabaseError;
+
+/// Metadata identified by a full-width digest besides its compact `FileHash`
+pub(crate) trait Digestible: Serialize + DeserializeOwned + Clone {
+    /// Copy of the metadata with the id cleared, the id depends on where the entry is stored
+    fn without_id(&self) -> Self;
+
+    /// FNV-1a 64 bit digest of every field but the id
+    fn digest(&self) -> Result<u64, DatabaseError> {
+        let data = bincode::serialize(&self.without_id())?;
+        Ok(fnv1a64(FNV_OFFSET_BASIS, &data))
+    }
+}
+
+impl Digestible for SongMetaData {
+    fn without_id(&self) -> Self {
+        SongMetaData {
+            id: 0,
+            ..self.clone()
+        }
+    }
+}
+
+impl Digestible for VideoMetaData {
+    fn without_id(&self) -> Self {
+        VideoMetaData {
+            id: 0,
+            ..self.clone()
+        }
+    }
+}
+
+/// Next key in the probing sequence of `file_hash`, 0 is skipped since it means "no id"
+fn next_probe(file_hash: FileHash) -> FileHash {
+    match file_hash.wrapping_add(1) {
+        0 => 1,
+        next => next,
+    }
+}
+
+/// Key of the digest index: the kind of the file, then its big-endian digest
+fn digest_index_key(kind: FileKind, digest: u64) -> [u8; 9] {
+    let mut key = [0; 9];
+    key[0] = kind as u8;
+    key[1..].copy_from_slice(&digest.to_be_bytes());
+    key
+}
+
+impl<T: Digestible> FileEntry<T> {
+    /// Creates an entry with the digest of `file_metadata`
+    pub(crate) fn new(file_metadata: T, peers: HashSet<NodeId>) -> Result<Self, DatabaseError> {
+        Ok(FileEntry {
+            digest: file_metadata.digest()?,
+            file_metadata,
+            peers,
+        })
+    }
+}
+
+impl Database {
+    /// Digest of the entry stored at `file_hash`, `None` if the key is free
+    fn stored_digest<T: Digestible>(
+        tree: &Tree,
+        file_hash: FileHash,
+    ) -> Result<Option<u64>, DatabaseError> {
+        match tree.get(entry_key(file_hash))? {
+            Some(data) => Ok(Some(decode::<FileEntry<T>>(&data)?.digest)),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the key of the file with `digest` by linear probing from `file_hash`:
+    /// the first key that is free or already holds the same file. The result only depends on the stored entries.
+    /// ### Error
+    /// Returns `DatabaseError::HashCollision` if every key holds another file.
+    fn probe_key<T: Digestible>(
+        tree: &Tree,
+        file_hash: FileHash,
+        digest: u64,
+    ) -> Result<FileHash, DatabaseError> {
+        let start = if file_hash == 0 { 1 } else { file_hash };
+        let mut candidate = start;
+        loop {
+            match Self::stored_digest::<T>(tree, candidate)? {
+                Some(stored) if stored != digest => {}
+                _ => return Ok(candidate),
+            }
+
+            candidate = next_probe(candidate);
+            if candidate == start {
+                return Err(DatabaseError::HashCollision(file_hash));
+            }
+        }
+    }
+
+    /// Key under which the file with `digest` is stored, `None` if it is not in the catalog
+    pub(crate) fn get_digest_key(
+        &self,
+        kind: FileKind,
+        digest: u64,
+    ) -> Result<Option<FileHash>, DatabaseError> {
+        Ok(self
+            .digest_keys_tree
+            .get(digest_index_key(kind, digest))?
+            .and_then(|data| decode_entry_key(&data)))
+    }
+
+    /// Key of the file with `metadata` if it is in the catalog, whatever key it was submitted with
+    pub(crate) fn find_key<T: Digestible + Searchable>(
+        &self,
+        metadata: &T,
+    ) -> Result<Option<FileHash>, DatabaseError> {
+        self.get_digest_key(T::KIND, metadata.digest()?)
+    }
+
+    /// Key of the file with `digest`: the one already assigned to it,
+    /// otherwise the first free key probing from `file_hash`.
+    /// A file whose `file_hash` holds another file is stored under a different key, looked up by its digest.
+    /// ### Error
+    /// Returns `DatabaseError::HashCollision` if every key holds another file.
+    pub(crate) fn assign_key<T: Digestible + Searchable>(
+        &self,
+        tree: &Tree,
+        file_hash: FileHash,
+        digest: u64,
+    ) -> Result<FileHash, DatabaseError> {
+        match self.get_digest_key(T::KIND, digest)? {
+            Some(file_hash) => Ok(file_hash),
+            None => Self::probe_key::<T>(tree, file_hash, digest),
+        }
+    }
+
+    /// Point the digest of the entry stored at `file_hash` to it,
+    /// replacing the `previous` digest if the metadata changed. A `current` of `None` removes the entry.
+    pub(crate) fn update_digest_index(
+        &self,
+        kind: FileKind,
+        file_hash: FileHash,
+        previous: Option<u64>,
+        current: Option<u64>,
+    ) -> Result<(), DatabaseError> {
+        if previous == current {
+            return Ok(());
+        }
+        if let Some(digest) = previous {
+            self.digest_keys_tree
+                .remove(digest_index_key(kind, digest))?;
+        }
+        if let Some(digest) = current {
+            self.digest_keys_tree
+                .insert(digest_index_key(kind, digest), &entry_key(file_hash))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::schema::encode;
+    use crate::database::DatabaseLocation;
+    use crate::test_fixtures::song;
+
+    fn store(database: &Database, key: FileHash, metadata: SongMetaData) {
+        let entry = FileEntry::new(metadata, HashSet::from([2])).unwrap();
+        database
+            .songs_tree
+            .insert(entry_key(key), encode(&entry).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn digest_ignores_the_id() {
+        assert_eq!(
+            song(1, "title").digest().unwrap(),
+            song(2, "title").digest().unwrap()
+        );
+        assert_ne!(
+            song(1, "title").digest().unwrap(),
+            song(1, "other").digest().unwrap()
+        );
+    }
+
+    #[test]
+    fn colliding_files_get_another_key() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        let first = song(7, "first");
+        database.insert_song_peer(&first, 2).unwrap();
+
+        // Same id, different metadata
+        let second = song(7, "second");
+        assert_eq!(database.insert_song_peer(&second, 3).unwrap(), 8);
+        assert_eq!(database.find_key(&second).unwrap(), Some(8));
+        assert_eq!(
+            database.get_song_entry(7).unwrap().peers,
+            HashSet::from([2])
+        );
+
+        // The file keeps its key when shared again
+        assert_eq!(database.insert_song_peer(&second, 4).unwrap(), 8);
+        assert_eq!(
+            database.get_song_entry(8).unwrap().peers,
+            HashSet::from([3, 4])
+        );
+
+        // The index forgets removed files
+        database.remove_song_peer(8, 3).unwrap();
+        database.remove_song_peer(8, 4).unwrap();
+        assert_eq!(database.find_key(&second).unwrap(), None);
+    }
+
+    #[test]
+    fn probes_the_next_free_key() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        store(&database, FileHash::MAX, song(FileHash::MAX, "first"));
+        store(&database, 1, song(1, "second"));
+        let tree = &database.songs_tree;
+
+        // The file already stored keeps its key
+        let first = song(0, "first").digest().unwrap();
+        assert_eq!(
+            Database::probe_key::<SongMetaData>(tree, FileHash::MAX, first).unwrap(),
+            FileHash::MAX
+        );
+
+        // Probing wraps around skipping 0
+        let third = song(0, "third").digest().unwrap();
+        assert_eq!(
+            Database::probe_key::<SongMetaData>(tree, FileHash::MAX, third).unwrap(),
+            2
+        );
+    }
+}
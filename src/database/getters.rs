@@ -1,49 +1,98 @@
 use packet_forge::{ClientType, FileHash, SongMetaData, VideoMetaData};
 use wg_internal::network::NodeId;
 
-use super::{construct_payload_key, Database, FileEntry};
+use super::keys::{entry_key, PayloadKey, PayloadKind};
+use super::schema::decode;
+use super::{checksum, Database, FileEntry, VideoChunksInfo};
+use crate::error::DatabaseError;
 
 impl Database {
     /// Retrieves song metadata from the database by ID.
-    pub(crate) fn get_song_entry(&self, id: FileHash) -> Result<FileEntry<SongMetaData>, String> {
-        self.songs_tree
-            .get(id.to_be_bytes())
-            .map_err(|e| format!("Error accessing database: {e}"))?
-            .ok_or_else(|| "Song not found".to_string())
-            .and_then(|data| {
-                bincode::deserialize(&data).map_err(|e| format!("Deserialization error: {e}"))
-            })
+    pub(crate) fn get_song_entry(
+        &self,
+        id: FileHash,
+    ) -> Result<FileEntry<SongMetaData>, DatabaseError> {
+        let data = self
+            .songs_tree
+            .get(entry_key(id))?
+            .ok_or(DatabaseError::SongNotFound(id))?;
+        decode(&data)
     }
 
     /// Retrieves video metadata from the database by ID.
-    pub(crate) fn get_video_entry(&self, id: FileHash) -> Result<FileEntry<VideoMetaData>, String> {
-        self.video_tree
-            .get(id.to_be_bytes())
-            .map_err(|e| format!("Error accessing database: {e}"))?
-            .ok_or_else(|| "Video not found".to_string())
-            .and_then(|data| {
-                bincode::deserialize(&data).map_err(|e| format!("Deserialization error: {e}"))
-            })
+    pub(crate) fn get_video_entry(
+        &self,
+        id: FileHash,
+    ) -> Result<FileEntry<VideoMetaData>, DatabaseError> {
+        let data = self
+            .video_tree
+            .get(entry_key(id))?
+            .ok_or(DatabaseError::VideoNotFound(id))?;
+        decode(&data)
     }
 
-    /// Retrieves song payload from the database by ID.
-    pub(crate) fn get_song_payload(&self, prefix: &str, id: FileHash) -> Result<Vec<u8>, String> {
-        let key = construct_payload_key(prefix, id);
-        self.songs_tree
-            .get(key)
-            .map_err(|e| format!("Error accessing database: {e}"))?
+    /// Retrieves a song segment from the database by ID, segment 0 is the playlist.
+    /// ### Error
+    /// Returns `DatabaseError::ChunkNotFound` if the segment is not stored.
+    pub(crate) fn get_song_segment(
+        &self,
+        id: FileHash,
+        index: u32,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        self.song_payloads_tree
+            .get(PayloadKey::segment(id, index).encode())?
             .map(|data| data.to_vec())
-            .ok_or_else(|| "Song payload not found".to_string())
+            .ok_or(DatabaseError::ChunkNotFound { file: id, index })
     }
 
-    /// Retrieves video payload from the database by ID.
-    pub(crate) fn get_video_payload(&self, id: FileHash) -> Result<Vec<u8>, String> {
-        let key = construct_payload_key("pl", id);
-        self.video_tree
-            .get(key)
-            .map_err(|e| format!("Error accessing database: {e}"))?
+    /// Returns the number of payload segments stored for a song, playlist included.
+    /// Segment keys are ordered by index, so the count is the index of the last one plus one.
+    pub(crate) fn get_song_segments_count(&self, id: FileHash) -> Result<u32, DatabaseError> {
+        let last = self
+            .song_payloads_tree
+            .scan_prefix(PayloadKey::kind_prefix(id, PayloadKind::Segment))
+            .next_back()
+            .transpose()?;
+
+        Ok(last
+            .and_then(|(key, _)| PayloadKey::decode(&key))
+            .map_or(0, |key| key.index + 1))
+    }
+
+    /// Retrieves the chunks layout of a video payload from the database by ID.
+    pub(crate) fn get_video_chunks_info(
+        &self,
+        id: FileHash,
+    ) -> Result<VideoChunksInfo, DatabaseError> {
+        let data = self
+            .video_payloads_tree
+            .get(PayloadKey::chunks_info(id).encode())?
+            .ok_or(DatabaseError::PayloadNotFound(id))?;
+        decode(&data)
+    }
+
+    /// Retrieves a single video chunk from the database and verifies its checksum against `info`.
+    pub(crate) fn get_video_chunk(
+        &self,
+        id: FileHash,
+        index: u32,
+        info: &VideoChunksInfo,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let not_found = DatabaseError::ChunkNotFound { file: id, index };
+        let Some(expected) = info.checksums.get(index as usize) else {
+            return Err(not_found);
+        };
+
+        let chunk = self
+            .video_payloads_tree
+            .get(PayloadKey::chunk(id, index).encode())?
             .map(|data| data.to_vec())
-            .ok_or_else(|| "Video payload not found".to_string())
+            .ok_or(not_found)?;
+
+        if checksum(&chunk) != *expected {
+            return Err(DatabaseError::ChecksumMismatch { file: id, index });
+        }
+        Ok(chunk)
     }
 
     /// Retrieves metadata for all songs in the database, excluding those shared by the specified node.
@@ -51,15 +100,9 @@ impl Database {
         self.songs_tree
             .iter()
             .filter_map(|entry| {
-                if let Ok((key, data)) = entry {
-                    if let Ok(key_str) = std::str::from_utf8(&key) {
-                        if key_str.starts_with("ts") {
-                            return None; // Skip entries where key starts with "ts"
-                        }
-                    }
-
+                if let Ok((_, data)) = entry {
                     // Attempt to deserialize the data
-                    if let Ok(file_entry) = bincode::deserialize::<FileEntry<SongMetaData>>(&data) {
+                    if let Ok(file_entry) = decode::<FileEntry<SongMetaData>>(&data) {
                         // Exclude entries where node_id is present in peers
                         if !file_entry.peers.contains(&node_id) {
                             return Some(file_entry.file_metadata);
@@ -76,16 +119,9 @@ impl Database {
         self.video_tree
             .iter()
             .filter_map(|entry| {
-                if let Ok((key, data)) = entry {
-                    if let Ok(key_str) = std::str::from_utf8(&key) {
-                        if key_str.starts_with("pl") {
-                            return None; // Skip entries where key starts with "pl"
-                        }
-                    }
-
+                if let Ok((_, data)) = entry {
                     // Attempt to deserialize the data
-                    if let Ok(file_entry) = bincode::deserialize::<FileEntry<VideoMetaData>>(&data)
-                    {
+                    if let Ok(file_entry) = decode::<FileEntry<VideoMetaData>>(&data) {
                         // Exclude entries where node_id is present in peers
                         if !file_entry.peers.contains(&node_id) {
                             return Some(file_entry.file_metadata);
@@ -106,13 +142,21 @@ impl Database {
         res.unwrap()
     }
 
-    pub(crate) fn get_client_type(&self, id: NodeId) -> Result<ClientType, String> {
+    /// Returns the IDs of every subscribed client
+    pub(crate) fn get_client_ids(&self) -> Result<Vec<NodeId>, DatabaseError> {
         self.clients_tree
-            .get(id.to_be_bytes())
-            .map_err(|e| format!("Error accessing database: {e}"))?
-            .ok_or_else(|| "Client not found. Subscribe to the server!".to_string())
-            .and_then(|data| {
-                bincode::deserialize(&data).map_err(|e| format!("Deserialization error: {e}"))
+            .iter()
+            .keys()
+            .filter_map(|key| match key {
+                Ok(key) => key.first().copied().map(Ok),
+                Err(e) => Some(Err(e.into())),
             })
+            .collect()
+    }
+
+    pub(crate) fn get_client_type(&self, id: NodeId) -> Result<ClientType, DatabaseError> {
+        self.get_client_info(id)?
+            .map(|client_info| client_info.client_type)
+            .ok_or(DatabaseError::ClientNotFound(id))
     }
 }
@@ -1,9 +1,14 @@
 use std::{collections::HashSet, fs};
 
-use packet_forge::{FileHash, Metadata, SongMetaData};
+use packet_forge::{FileHash, FileMetadata, Metadata, SongMetaData};
 use wg_internal::network::NodeId;
 
-use super::{construct_payload_key, Database, FileEntry};
+use super::digest::Digestible;
+use super::keys::{entry_key, PayloadKey};
+use super::local_files::{list_files, local_fingerprint, LocalFileRecord, SONG_RECORD_PREFIX};
+use super::schema::{decode, encode};
+use super::{Database, FileEntry, FileKind};
+use crate::error::DatabaseError;
 
 impl Database {
     /// Insert a `FileEntry` for `SongMetaData` into the `songs_tree`
@@ -11,159 +16,241 @@ impl Database {
         &self,
         mut file_hash: FileHash,
         file_entry: &mut FileEntry<SongMetaData>,
-    ) -> Result<FileHash, String> {
+    ) -> Result<FileHash, DatabaseError> {
         if file_hash == 0 {
             file_hash = file_entry.file_metadata.compact_hash_u16();
             file_entry.file_metadata.id = file_hash;
         }
 
-        let serialized_entry =
-            bincode::serialize(&file_entry).map_err(|e| format!("Serialization error: {e}"))?;
-        self.songs_tree
-            .insert(file_hash.to_be_bytes(), serialized_entry)
-            .map(|_| file_hash)
-            .map_err(|e| format!("Error inserting song metadata: {e}"))
+        let serialized_entry = encode(&file_entry)?;
+        let previous = self
+            .songs_tree
+            .insert(entry_key(file_hash), serialized_entry)?
+            .map(|data| decode::<FileEntry<SongMetaData>>(&data))
+            .transpose()?;
+
+        self.update_search_index(
+            file_hash,
+            previous.as_ref().map(|entry| &entry.file_metadata),
+            Some(&file_entry.file_metadata),
+        )?;
+
+        let previous_digest = previous.map(|entry| entry.digest);
+        self.update_digest_index(
+            FileKind::Song,
+            file_hash,
+            previous_digest,
+            Some(file_entry.digest),
+        )?;
+
+        // A new peer of an unchanged file is not a catalog change
+        if previous_digest != Some(file_entry.digest) {
+            self.record_catalog_change(FileKind::Song, file_hash, None)?;
+        }
+        Ok(file_hash)
     }
 
-    /// Inserts song payload into the database.
-    fn insert_song_payload(
+    /// Inserts a song segment into the database, segment 0 is the playlist.
+    fn insert_song_segment(
         &self,
-        prefix: &str,
         id: FileHash,
+        index: u32,
         payload: Vec<u8>,
-    ) -> Result<(), String> {
-        let key = construct_payload_key(prefix, id);
-        match self.songs_tree.insert(key, payload) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Error inserting song payload: {e}")),
-        }
+    ) -> Result<(), DatabaseError> {
+        self.song_payloads_tree
+            .insert(PayloadKey::segment(id, index).encode(), payload)?;
+        Ok(())
     }
 
-    /// Insert a vector of `SongMetaData` inside `songs_tree`.
+    /// Insert a vector of local `SongMetaData` inside `songs_tree`.
+    /// Songs whose metadata and segment files did not change since the last ingest are skipped,
+    /// songs that are no longer in the vector stop being hosted by the server.
     pub(crate) fn insert_songs_from_vec(
         &self,
         local_path: &str,
         songs: &Vec<SongMetaData>,
-    ) -> Result<(), String> {
+    ) -> Result<(), DatabaseError> {
+        let mut seen = HashSet::new();
+
         for song in songs {
-            let mut file_entry = FileEntry {
-                file_metadata: song.clone(),
-                peers: HashSet::from([self.server_id]),
+            let song_title_parsed = song.title.replace(' ', "").to_lowercase();
+            let record_key = format!("{SONG_RECORD_PREFIX}{song_title_parsed}");
+            seen.insert(record_key.clone());
+
+            let song_parts = list_files(&format!("{local_path}/songs/{song_title_parsed}"))?;
+            let fingerprint = local_fingerprint(song, &song_parts)?;
+
+            if let Some(record) = self.get_local_file(&record_key)? {
+                if record.fingerprint == fingerprint {
+                    continue; // Unchanged since the last ingest
+                }
+                self.unhost_song(record.file_hash)?;
+            }
+
+            let song_id = if song.id == 0 {
+                song.compact_hash_u16()
+            } else {
+                song.id
             };
+            // A different song may already use the id, take the next free one
+            let song_id =
+                self.assign_key::<SongMetaData>(&self.songs_tree, song_id, song.digest()?)?;
+            let mut file_entry = FileEntry::new(song.clone(), HashSet::from([self.server_id]))?;
+            file_entry.file_metadata.id = song_id;
 
-            let song_id: FileHash = self.insert_song_file_entry(song.id, &mut file_entry)?;
+            // Keep the clients already sharing the song
+            if let Ok(existing) = self.get_song_entry(song_id) {
+                file_entry.peers.extend(existing.peers);
+            }
 
-            let song_title_parsed = song.title.replace(' ', "").to_lowercase();
+            self.insert_song_file_entry(song_id, &mut file_entry)?;
+            self.add_peer_file(self.server_id, FileKind::Song, song_id)?;
 
-            let song_parts = fs::read_dir(format!("{local_path}/songs/{song_title_parsed}"))
-                .map_err(|e| format!("Error reading directory {local_path}: {e}"))?;
-
-            for part in song_parts {
-                let part = part.map_err(|e| format!("Error reading directory part: {e}"))?;
-                let path = part.path();
-                if path.is_file() {
-                    let entry_content = fs::read(&path).map_err(|e| {
-                        format!("Error reading segment file {}: {}", path.display(), e)
-                    })?;
-
-                    if path.extension().and_then(|ext| ext.to_str()) == Some("m3u8") {
-                        self.insert_song_payload("ts0", song_id, entry_content)?;
-                    } else if path.extension().and_then(|ext| ext.to_str()) == Some("ts") {
-                        let segment = path
-                            .file_stem()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_string()
-                            .replace("segment", "")
-                            .parse::<u16>()
-                            .unwrap()
-                            + 1;
-
-                        let prefix = &format!("ts{segment}");
-                        self.insert_song_payload(prefix, song_id, entry_content)?;
-                    }
-                    // CONTINUE SKIP INVALID FILE EXTENSION
-                    // TODO use logger
-                    // return Err("Error: Invalid file extension".to_string());
+            for path in song_parts {
+                let entry_content = fs::read(&path).map_err(|source| DatabaseError::Io {
+                    path: path.display().to_string(),
+                    source,
+                })?;
+
+                if path.extension().and_then(|ext| ext.to_str()) == Some("m3u8") {
+                    self.insert_song_segment(song_id, 0, entry_content)?;
+                } else if path.extension().and_then(|ext| ext.to_str()) == Some("ts") {
+                    let segment = path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .and_then(|stem| stem.replace("segment", "").parse::<u32>().ok())
+                        .ok_or_else(|| {
+                            DatabaseError::InvalidSegmentName(path.display().to_string())
+                        })?
+                        + 1;
+
+                    self.insert_song_segment(song_id, segment, entry_content)?;
                 }
+                // CONTINUE SKIP INVALID FILE EXTENSION
             }
+
+            self.insert_local_file(
+                &record_key,
+                &LocalFileRecord {
+                    file_hash: song_id,
+                    fingerprint,
+                },
+            )?;
+        }
+
+        // Local songs that have been removed since the last ingest
+        for record in self.take_stale_local_files(SONG_RECORD_PREFIX, &seen)? {
+            self.unhost_song(record.file_hash)?;
         }
         Ok(())
     }
 
+    /// Stop hosting a local song: remove its segments and the server from its peers.
+    /// The entry is removed if no other peer shares the song.
+    pub(crate) fn unhost_song(&self, id: FileHash) -> Result<(), DatabaseError> {
+        for entry in self
+            .song_payloads_tree
+            .scan_prefix(PayloadKey::file_prefix(id))
+        {
+            let (key, _) = entry?;
+            self.song_payloads_tree.remove(key)?;
+        }
+
+        self.remove_song_peer(id, self.server_id)
+    }
+
     /// Add the `peer_id` to the entry `song_metadata` in the database. If not present inserts a new entry.
+    /// A different song may already be stored under the id of `song_metadata`, the song then gets the next free key.
+    /// Returns the key of the song.
+    /// ### Error
+    /// Returns `DatabaseError::HashCollision` if every key holds another song.
     pub(crate) fn insert_song_peer(
         &self,
         song_metadata: &SongMetaData,
         peer_id: NodeId,
-    ) -> Result<(), String> {
+    ) -> Result<FileHash, DatabaseError> {
+        let song_id = if song_metadata.id == 0 {
+            song_metadata.compact_hash_u16()
+        } else {
+            song_metadata.id
+        };
+        // Never overwrite a different song stored under the same id
+        let song_id =
+            self.assign_key::<SongMetaData>(&self.songs_tree, song_id, song_metadata.digest()?)?;
+
         // Attempt to retrieve the existing song entry
-        let mut file_entry = if let Ok(mut entry) = self.get_song_entry(song_metadata.id) {
+        let mut file_entry = if let Ok(mut entry) = self.get_song_entry(song_id) {
             // Add the client to the peers if the entry exists
             entry.peers.insert(peer_id);
             entry
         } else {
             // If the entry does not exist, create a new FileEntry
-            FileEntry {
-                file_metadata: song_metadata.clone(),
-                peers: HashSet::from([peer_id]),
-            }
+            let mut entry = FileEntry::new(song_metadata.clone(), HashSet::from([peer_id]))?;
+            entry.file_metadata.id = song_id;
+            entry
         };
 
         // Update or insert the FileEntry in the songs_tree
-        self.insert_song_file_entry(song_metadata.id, &mut file_entry)?;
-
-        Ok(())
+        self.insert_song_file_entry(song_id, &mut file_entry)?;
+        self.add_peer_file(peer_id, FileKind::Song, song_id)?;
+        Ok(song_id)
     }
 
-    pub(crate) fn remove_song(&self, peer_id: FileHash) -> Result<(), String> {
-        if let Err(msg) = self.songs_tree.remove(peer_id.to_be_bytes()) {
-            return Err(format!("An error occurred while removing a song: {msg}"));
+    /// Remove `peer_id` from the peers of the song.
+    /// The entry is dropped once no peer shares the song and the server does not host its payload.
+    pub(crate) fn remove_song_peer(
+        &self,
+        id: FileHash,
+        peer_id: NodeId,
+    ) -> Result<(), DatabaseError> {
+        self.remove_peer_file(peer_id, FileKind::Song, id)?;
+
+        let mut file_entry = match self.get_song_entry(id) {
+            Ok(file_entry) => file_entry,
+            Err(DatabaseError::SongNotFound(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !file_entry.peers.remove(&peer_id) {
+            return Ok(()); // The peer was not sharing the song
+        }
+
+        if file_entry.peers.is_empty() && !self.hosts_song_payload(id)? {
+            self.songs_tree.remove(entry_key(id))?;
+            self.update_search_index(id, Some(&file_entry.file_metadata), None)?;
+            self.update_digest_index(FileKind::Song, id, Some(file_entry.digest), None)?;
+            let removed = FileMetadata::Song(file_entry.file_metadata);
+            self.record_catalog_change(FileKind::Song, id, Some(&removed))?;
+            return Ok(());
         }
+
+        // Re-insertion with edited peer list
+        self.insert_song_file_entry(id, &mut file_entry)?;
         Ok(())
     }
 
-    pub(crate) fn remove_peer_from_songs(&self, peer_id: NodeId) -> Result<(), String> {
-        let mut errors: Vec<String> = Vec::new();
-
-        // Process songs_tree
-        for entry in &self.songs_tree {
-            match entry {
-                Ok((_, value)) => {
-                    let mut file_entry: FileEntry<SongMetaData> = match bincode::deserialize(&value)
-                    {
-                        Ok(fe) => fe,
-                        Err(e) => {
-                            errors.push(format!("Deserialization error: {e}"));
-                            continue; // Skip this entry
-                        }
-                    };
-
-                    // Remove the peer if it exists
-                    if file_entry.peers.remove(&peer_id) {
-                        // Re-insertion with edited peer list
-                        if let Err(e) = self
-                            .insert_song_file_entry(file_entry.file_metadata.id, &mut file_entry)
-                        {
-                            errors.push(format!("Error updating song entry: {e}"));
-                        }
-                    }
-                }
-                Err(e) => {
-                    errors.push(format!("Error iterating songs_tree: {e}"));
-                }
+    /// Returns true if the server stores segments of the song
+    fn hosts_song_payload(&self, id: FileHash) -> Result<bool, DatabaseError> {
+        let first = self
+            .song_payloads_tree
+            .scan_prefix(PayloadKey::file_prefix(id))
+            .next()
+            .transpose()?;
+        Ok(first.is_some())
+    }
+
+    /// Remove `peer_id` from the peers of the songs it shares, looked up in the peer index.
+    pub(crate) fn remove_peer_from_songs(&self, peer_id: NodeId) -> Result<(), DatabaseError> {
+        let mut errors: Vec<DatabaseError> = Vec::new();
+
+        for song_id in self.get_peer_files(peer_id, FileKind::Song)? {
+            if let Err(e) = self.remove_song_peer(song_id, peer_id) {
+                errors.push(e);
             }
         }
-        // Log or handle collected errors
+
+        // Return the collected errors to the caller
         if !errors.is_empty() {
-            for error in &errors {
-                // TODO use logger
-                eprintln!("{error}"); // Log errors
-            }
-            return Err(format!(
-                "Remove peer from songs completed with {} errors",
-                errors.len()
-            ));
+            return Err(DatabaseError::Multiple(errors));
         }
 
         Ok(())
@@ -1,9 +1,14 @@
-use std::{collections::HashSet, fs};
+use std::{collections::HashSet, fs, path::PathBuf};
 
-use packet_forge::{FileHash, Metadata, VideoMetaData};
+use packet_forge::{FileHash, FileMetadata, Metadata, VideoMetaData};
 use wg_internal::network::NodeId;
 
-use super::{construct_payload_key, Database, FileEntry};
+use super::digest::Digestible;
+use super::keys::{entry_key, PayloadKey};
+use super::local_files::{local_fingerprint, LocalFileRecord, VIDEO_RECORD_PREFIX};
+use super::schema::{decode, encode};
+use super::{checksum, Database, FileEntry, FileKind, VideoChunksInfo, VIDEO_CHUNK_SIZE};
+use crate::error::DatabaseError;
 
 impl Database {
     /// Insert a `FileEntry` for `VideoMetaData` into the `video_tree`
@@ -11,127 +16,242 @@ impl Database {
         &self,
         mut file_hash: FileHash,
         file_entry: &mut FileEntry<VideoMetaData>,
-    ) -> Result<FileHash, String> {
+    ) -> Result<FileHash, DatabaseError> {
         if file_hash == 0 {
             file_hash = file_entry.file_metadata.compact_hash_u16();
             file_entry.file_metadata.id = file_hash;
         }
 
-        let serialized_entry =
-            bincode::serialize(&file_entry).map_err(|e| format!("Serialization error: {e}"))?;
-        self.video_tree
-            .insert(file_hash.to_be_bytes(), serialized_entry)
-            .map(|_| file_hash)
-            .map_err(|e| format!("Error inserting song metadata: {e}"))
+        let serialized_entry = encode(&file_entry)?;
+        let previous = self
+            .video_tree
+            .insert(entry_key(file_hash), serialized_entry)?
+            .map(|data| decode::<FileEntry<VideoMetaData>>(&data))
+            .transpose()?;
+
+        self.update_search_index(
+            file_hash,
+            previous.as_ref().map(|entry| &entry.file_metadata),
+            Some(&file_entry.file_metadata),
+        )?;
+
+        let previous_digest = previous.map(|entry| entry.digest);
+        self.update_digest_index(
+            FileKind::Video,
+            file_hash,
+            previous_digest,
+            Some(file_entry.digest),
+        )?;
+
+        // A new peer of an unchanged file is not a catalog change
+        if previous_digest != Some(file_entry.digest) {
+            self.record_catalog_change(FileKind::Video, file_hash, None)?;
+        }
+        Ok(file_hash)
     }
 
-    /// Inserts video payload into the database.
-    fn insert_video_payload(&self, id: FileHash, payload: Vec<u8>) -> Result<(), String> {
-        let key = construct_payload_key("pl", id);
-        match self.video_tree.insert(key, payload) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Error inserting song payload: {e}")),
+    /// Inserts video payload into the database, split in `VIDEO_CHUNK_SIZE` chunks:
+    /// - each chunk is stored under a `PayloadKind::Chunk` key
+    /// - the chunks layout is stored under the `PayloadKind::ChunksInfo` key
+    pub(crate) fn insert_video_payload(
+        &self,
+        id: FileHash,
+        payload: &[u8],
+    ) -> Result<(), DatabaseError> {
+        let mut checksums = Vec::new();
+        for (index, chunk) in payload.chunks(VIDEO_CHUNK_SIZE).enumerate() {
+            let index = u32::try_from(index).map_err(|_| DatabaseError::PayloadTooLarge(id))?;
+            self.video_payloads_tree
+                .insert(PayloadKey::chunk(id, index).encode(), chunk)?;
+            checksums.push(checksum(chunk));
         }
+
+        let info = VideoChunksInfo {
+            n_chunks: u32::try_from(checksums.len())
+                .map_err(|_| DatabaseError::PayloadTooLarge(id))?,
+            chunk_size: VIDEO_CHUNK_SIZE as u32,
+            total_size: payload.len() as u64,
+            checksums,
+        };
+        let serialized_info = encode(&info)?;
+        self.video_payloads_tree
+            .insert(PayloadKey::chunks_info(id).encode(), serialized_info)?;
+        Ok(())
     }
 
-    /// Insert a vector of `VideoMetaData` inside `video_tree`.
+    /// Insert a vector of local `VideoMetaData` inside `video_tree`.
+    /// Videos whose metadata and file did not change since the last ingest are skipped,
+    /// videos that are no longer in the vector stop being hosted by the server.
     pub(crate) fn insert_videos_from_vec(
         &self,
         local_path: &str,
         videos: &Vec<VideoMetaData>,
-    ) -> Result<(), String> {
+    ) -> Result<(), DatabaseError> {
+        let mut seen = HashSet::new();
+
         for video in videos {
-            let mut file_entry = FileEntry {
-                file_metadata: video.clone(),
-                peers: HashSet::from([self.server_id]),
+            let video_title_parsed = video.title.replace(' ', "").to_lowercase();
+            let record_key = format!("{VIDEO_RECORD_PREFIX}{video_title_parsed}");
+            seen.insert(record_key.clone());
+
+            let video_file_path = format!("{local_path}/videos/{video_title_parsed}.mp4");
+            let fingerprint = local_fingerprint(video, &[PathBuf::from(&video_file_path)])?;
+
+            if let Some(record) = self.get_local_file(&record_key)? {
+                if record.fingerprint == fingerprint {
+                    continue; // Unchanged since the last ingest
+                }
+                self.unhost_video(record.file_hash)?;
+            }
+
+            let video_id = if video.id == 0 {
+                video.compact_hash_u16()
+            } else {
+                video.id
             };
+            // A different video may already use the id, take the next free one
+            let video_id =
+                self.assign_key::<VideoMetaData>(&self.video_tree, video_id, video.digest()?)?;
+            let mut file_entry = FileEntry::new(video.clone(), HashSet::from([self.server_id]))?;
+            file_entry.file_metadata.id = video_id;
 
-            let video_id = self.insert_video_file_entry(video.id, &mut file_entry)?;
+            // Keep the clients already sharing the video
+            if let Ok(existing) = self.get_video_entry(video_id) {
+                file_entry.peers.extend(existing.peers);
+            }
 
-            let video_title_parsed = video.title.replace(' ', "").to_lowercase();
-            let video_file_path = format!("{local_path}/videos/{video_title_parsed}.mp4");
+            self.insert_video_file_entry(video_id, &mut file_entry)?;
+            self.add_peer_file(self.server_id, FileKind::Video, video_id)?;
+
+            let video_content = fs::read(&video_file_path).map_err(|source| DatabaseError::Io {
+                path: video_file_path.clone(),
+                source,
+            })?;
+
+            self.insert_video_payload(video_id, &video_content)?;
 
-            let video_content = fs::read(&video_file_path)
-                .map_err(|e| format!("Error reading video file {video_file_path}: {e}"))?;
+            self.insert_local_file(
+                &record_key,
+                &LocalFileRecord {
+                    file_hash: video_id,
+                    fingerprint,
+                },
+            )?;
+        }
 
-            self.insert_video_payload(video_id, video_content)?;
+        // Local videos that have been removed since the last ingest
+        for record in self.take_stale_local_files(VIDEO_RECORD_PREFIX, &seen)? {
+            self.unhost_video(record.file_hash)?;
         }
         Ok(())
     }
 
+    /// Stop hosting a local video: remove its chunks and the server from its peers.
+    /// The entry is removed if no other peer shares the video.
+    pub(crate) fn unhost_video(&self, id: FileHash) -> Result<(), DatabaseError> {
+        for entry in self
+            .video_payloads_tree
+            .scan_prefix(PayloadKey::file_prefix(id))
+        {
+            let (key, _) = entry?;
+            self.video_payloads_tree.remove(key)?;
+        }
+
+        self.remove_video_peer(id, self.server_id)
+    }
+
     /// Add the `peer_id` to the entry `video_metadata` in the database. If not present inserts a new entry.
+    /// A different video may already be stored under the id of `video_metadata`, the video then gets the next free key.
+    /// Returns the key of the video.
+    /// ### Error
+    /// Returns `DatabaseError::HashCollision` if every key holds another video.
     pub(crate) fn insert_video_peer(
         &self,
         video_metadata: &VideoMetaData,
         peer_id: NodeId,
-    ) -> Result<(), String> {
-        // Attempt to retrieve the existing song entry
-        let mut file_entry = if let Ok(mut entry) = self.get_video_entry(video_metadata.id) {
+    ) -> Result<FileHash, DatabaseError> {
+        let video_id = if video_metadata.id == 0 {
+            video_metadata.compact_hash_u16()
+        } else {
+            video_metadata.id
+        };
+        // Never overwrite a different video stored under the same id
+        let video_id =
+            self.assign_key::<VideoMetaData>(&self.video_tree, video_id, video_metadata.digest()?)?;
+
+        // Attempt to retrieve the existing video entry
+        let mut file_entry = if let Ok(mut entry) = self.get_video_entry(video_id) {
             // Add the client to the peers if the entry exists
             entry.peers.insert(peer_id);
             entry
         } else {
             // If the entry does not exist, create a new FileEntry
-            FileEntry {
-                file_metadata: video_metadata.clone(),
-                peers: HashSet::from([peer_id]),
-            }
+            let mut entry = FileEntry::new(video_metadata.clone(), HashSet::from([peer_id]))?;
+            entry.file_metadata.id = video_id;
+            entry
         };
 
         // Update or insert the FileEntry in the video_tree
-        self.insert_video_file_entry(video_metadata.id, &mut file_entry)?;
-
-        Ok(())
+        self.insert_video_file_entry(video_id, &mut file_entry)?;
+        self.add_peer_file(peer_id, FileKind::Video, video_id)?;
+        Ok(video_id)
     }
 
-    pub(crate) fn remove_video(&self, peer_id: FileHash) -> Result<(), String> {
-        if let Err(msg) = self.video_tree.remove(peer_id.to_be_bytes()) {
-            return Err(format!("An error occurred while removing a video: {msg}"));
+    /// Remove `peer_id` from the peers of the video.
+    /// The entry is dropped once no peer shares the video and the server does not host its payload.
+    pub(crate) fn remove_video_peer(
+        &self,
+        id: FileHash,
+        peer_id: NodeId,
+    ) -> Result<(), DatabaseError> {
+        self.remove_peer_file(peer_id, FileKind::Video, id)?;
+
+        let mut file_entry = match self.get_video_entry(id) {
+            Ok(file_entry) => file_entry,
+            Err(DatabaseError::VideoNotFound(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !file_entry.peers.remove(&peer_id) {
+            return Ok(()); // The peer was not sharing the video
+        }
+
+        if file_entry.peers.is_empty() && !self.hosts_video_payload(id)? {
+            self.video_tree.remove(entry_key(id))?;
+            self.update_search_index(id, Some(&file_entry.file_metadata), None)?;
+            self.update_digest_index(FileKind::Video, id, Some(file_entry.digest), None)?;
+            let removed = FileMetadata::Video(file_entry.file_metadata);
+            self.record_catalog_change(FileKind::Video, id, Some(&removed))?;
+            return Ok(());
         }
+
+        // Re-insertion with edited peer list
+        self.insert_video_file_entry(id, &mut file_entry)?;
         Ok(())
     }
 
-    pub(crate) fn remove_peer_from_videos(&self, peer_id: NodeId) -> Result<(), String> {
-        let mut errors: Vec<String> = Vec::new();
-
-        // Process video_tree
-        for entry in &self.video_tree {
-            match entry {
-                Ok((_, value)) => {
-                    let mut file_entry: FileEntry<VideoMetaData> =
-                        match bincode::deserialize(&value) {
-                            Ok(fe) => fe,
-                            Err(e) => {
-                                errors.push(format!("Deserialization error: {e}"));
-                                continue; // Skip this entry
-                            }
-                        };
-
-                    // Remove the peer if it exists
-                    if file_entry.peers.remove(&peer_id) {
-                        // Re-insertion with edited peer list
-                        if let Err(e) = self
-                            .insert_video_file_entry(file_entry.file_metadata.id, &mut file_entry)
-                        {
-                            errors.push(format!("Error updating video entry: {e}"));
-                        }
-                    }
-                }
-                Err(e) => {
-                    errors.push(format!("Error iterating video_tree: {e}"));
-                }
+    /// Returns true if the server stores chunks of the video
+    fn hosts_video_payload(&self, id: FileHash) -> Result<bool, DatabaseError> {
+        let first = self
+            .video_payloads_tree
+            .scan_prefix(PayloadKey::file_prefix(id))
+            .next()
+            .transpose()?;
+        Ok(first.is_some())
+    }
+
+    /// Remove `peer_id` from the peers of the videos it shares, looked up in the peer index.
+    pub(crate) fn remove_peer_from_videos(&self, peer_id: NodeId) -> Result<(), DatabaseError> {
+        let mut errors: Vec<DatabaseError> = Vec::new();
+
+        for video_id in self.get_peer_files(peer_id, FileKind::Video)? {
+            if let Err(e) = self.remove_video_peer(video_id, peer_id) {
+                errors.push(e);
             }
         }
-        // Log or handle collected errors
+
+        // Return the collected errors to the caller
         if !errors.is_empty() {
-            for error in &errors {
-                // TODO use logger
-                eprintln!("{error}"); // Log errors
-            }
-            return Err(format!(
-                "Remove peer from video completed with {} errors",
-                errors.len()
-            ));
+            return Err(DatabaseError::Multiple(errors));
         }
 
         Ok(())
@@ -0,0 +1,139 @@
+use packet_forge::FileHash;
+
+/// Length of an encoded `PayloadKey`
+const PAYLOAD_KEY_LEN: usize = 7;
+
+/// Key of an entry in the `songs_tree`/`video_tree`: the big-endian `FileHash`
+pub(crate) fn entry_key(file: FileHash) -> [u8; 2] {
+    file.to_be_bytes()
+}
+
+/// Decode a key written by `entry_key`
+pub(crate) fn decode_entry_key(key: &[u8]) -> Option<FileHash> {
+    Some(FileHash::from_be_bytes(key.try_into().ok()?))
+}
+
+/// Kind of value stored in a payload tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PayloadKind {
+    /// A song segment: index 0 is the `m3u8` playlist, the following ones the `ts` segments
+    Segment = 0,
+    /// The `VideoChunksInfo` of a video
+    ChunksInfo = 1,
+    /// A video chunk of `VIDEO_CHUNK_SIZE` bytes
+    Chunk = 2,
+}
+
+impl PayloadKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PayloadKind::Segment),
+            1 => Some(PayloadKind::ChunksInfo),
+            2 => Some(PayloadKind::Chunk),
+            _ => None,
+        }
+    }
+}
+
+/// Key of the `song_payloads_tree`/`video_payloads_tree`, encoded as `[file BE][kind][index BE]`.
+/// The payloads of a file share the `file` prefix and are ordered by kind and index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PayloadKey {
+    pub file: FileHash,
+    pub kind: PayloadKind,
+    pub index: u32,
+}
+
+impl PayloadKey {
+    pub fn segment(file: FileHash, index: u32) -> Self {
+        PayloadKey {
+            file,
+            kind: PayloadKind::Segment,
+            index,
+        }
+    }
+
+    pub fn chunks_info(file: FileHash) -> Self {
+        PayloadKey {
+            file,
+            kind: PayloadKind::ChunksInfo,
+            index: 0,
+        }
+    }
+
+    pub fn chunk(file: FileHash, index: u32) -> Self {
+        PayloadKey {
+            file,
+            kind: PayloadKind::Chunk,
+            index,
+        }
+    }
+
+    /// Prefix shared by every payload of `file`
+    pub fn file_prefix(file: FileHash) -> [u8; 2] {
+        file.to_be_bytes()
+    }
+
+    /// Prefix shared by the payloads of `file` of the given kind
+    pub fn kind_prefix(file: FileHash, kind: PayloadKind) -> [u8; 3] {
+        let [high, low] = file.to_be_bytes();
+        [high, low, kind as u8]
+    }
+
+    pub fn encode(&self) -> [u8; PAYLOAD_KEY_LEN] {
+        let mut key = [0; PAYLOAD_KEY_LEN];
+        key[..3].copy_from_slice(&Self::kind_prefix(self.file, self.kind));
+        key[3..].copy_from_slice(&self.index.to_be_bytes());
+        key
+    }
+
+    /// Returns `None` if `key` was not written by `encode`
+    pub fn decode(key: &[u8]) -> Option<Self> {
+        let key: &[u8; PAYLOAD_KEY_LEN] = key.try_into().ok()?;
+        Some(PayloadKey {
+            file: FileHash::from_be_bytes([key[0], key[1]]),
+            kind: PayloadKind::from_u8(key[2])?,
+            index: u32::from_be_bytes([key[3], key[4], key[5], key[6]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_key_round_trip() {
+        for key in [
+            PayloadKey::segment(0x1234, 0),
+            PayloadKey::chunks_info(0xffff),
+            PayloadKey::chunk(1, u32::MAX),
+        ] {
+            assert_eq!(PayloadKey::decode(&key.encode()), Some(key));
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_keys() {
+        assert_eq!(PayloadKey::decode(b"pl3:1234"), None);
+        assert_eq!(PayloadKey::decode(&[0, 1, 9, 0, 0, 0, 0]), None);
+        assert_eq!(decode_entry_key(&entry_key(42)), Some(42));
+        assert_eq!(decode_entry_key(b"plinfo:42"), None);
+    }
+
+    #[test]
+    fn payloads_of_a_file_share_its_prefix_in_order() {
+        let file = 0x0102;
+        let keys = [
+            PayloadKey::segment(file, 1).encode(),
+            PayloadKey::chunks_info(file).encode(),
+            PayloadKey::chunk(file, 0).encode(),
+            PayloadKey::chunk(file, 256).encode(),
+        ];
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(keys
+            .iter()
+            .all(|key| key.starts_with(&PayloadKey::file_prefix(file))));
+        assert!(keys[2].starts_with(&PayloadKey::kind_prefix(file, PayloadKind::Chunk)));
+    }
+}
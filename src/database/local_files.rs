@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use packet_forge::FileHash;
+use serde::{Deserialize, Serialize};
+
+use super::schema::{decode, encode};
+use super::Database;
+use crate::error::DatabaseError;
+
+/// Key prefix of the local songs records
+pub(crate) const SONG_RECORD_PREFIX: &str = "song:";
+/// Key prefix of the local videos records
+pub(crate) const VIDEO_RECORD_PREFIX: &str = "video:";
+
+pub(crate) const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// A local file ingested by the server, used to skip the files that did not change since the last start
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalFileRecord {
+    pub file_hash: FileHash,
+    pub fingerprint: u64,
+}
+
+/// Continue a FNV-1a 64 bit hash with `data`
+pub(crate) fn fnv1a64(mut hash: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Returns the files inside `dir` sorted by path
+pub(crate) fn list_files(dir: &str) -> Result<Vec<PathBuf>, DatabaseError> {
+    let io_error = |source: io::Error| DatabaseError::Io {
+        path: dir.to_string(),
+        source,
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Fingerprint of a local file: its metadata plus the name, size and modification time of each payload file.
+pub(crate) fn local_fingerprint<T: Serialize>(
+    metadata: &T,
+    files: &[PathBuf],
+) -> Result<u64, DatabaseError> {
+    let mut hash = fnv1a64(FNV_OFFSET_BASIS, &bincode::serialize(metadata)?);
+
+    for path in files {
+        let file_metadata = fs::metadata(path).map_err(|source| DatabaseError::Io {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let modified = file_metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_nanos());
+
+        hash = fnv1a64(hash, path.to_string_lossy().as_bytes());
+        hash = fnv1a64(hash, &file_metadata.len().to_be_bytes());
+        hash = fnv1a64(hash, &modified.to_be_bytes());
+    }
+    Ok(hash)
+}
+
+impl Database {
+    pub(crate) fn get_local_file(
+        &self,
+        key: &str,
+    ) -> Result<Option<LocalFileRecord>, DatabaseError> {
+        match self.local_files_tree.get(key)? {
+            Some(data) => Ok(Some(decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn insert_local_file(
+        &self,
+        key: &str,
+        record: &LocalFileRecord,
+    ) -> Result<(), DatabaseError> {
+        self.local_files_tree.insert(key, encode(record)?)?;
+        Ok(())
+    }
+
+    /// Remove the records under `prefix` whose key is not in `seen`: the local file does not exist anymore.
+    /// Returns the removed records.
+    pub(crate) fn take_stale_local_files(
+        &self,
+        prefix: &str,
+        seen: &HashSet<String>,
+    ) -> Result<Vec<LocalFileRecord>, DatabaseError> {
+        let mut stale = Vec::new();
+        for entry in self.local_files_tree.scan_prefix(prefix) {
+            let (key, data) = entry?;
+            if seen.contains(String::from_utf8_lossy(&key).as_ref()) {
+                continue;
+            }
+            self.local_files_tree.remove(&key)?;
+            stale.push(decode(&data)?);
+        }
+        Ok(stale)
+    }
+}
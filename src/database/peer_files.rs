@@ -0,0 +1,75 @@
+use packet_forge::FileHash;
+use wg_internal::network::NodeId;
+
+use super::schema::{decode, encode};
+use super::{ClientInfo, Database, FileKind};
+use crate::error::DatabaseError;
+
+/// Key of the `peer_files_tree`: `[peer_id][kind][file_hash BE]`, so the files of a peer share a prefix
+pub(crate) fn peer_file_key(peer_id: NodeId, kind: FileKind, file_hash: FileHash) -> Vec<u8> {
+    let mut key = vec![peer_id, kind as u8];
+    key.extend_from_slice(&file_hash.to_be_bytes());
+    key
+}
+
+impl Database {
+    /// Record that `peer_id` shares the file, in the peer index and in the `ClientInfo` of the peer if it is a client.
+    pub(crate) fn add_peer_file(
+        &self,
+        peer_id: NodeId,
+        kind: FileKind,
+        file_hash: FileHash,
+    ) -> Result<(), DatabaseError> {
+        self.peer_files_tree
+            .insert(peer_file_key(peer_id, kind, file_hash), &[])?;
+
+        if let Some(mut client_info) = self.get_client_info(peer_id)? {
+            if client_info.shared_files.insert(file_hash) {
+                self.clients_tree
+                    .insert(peer_id.to_be_bytes(), encode(&client_info)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that `peer_id` does not share the file anymore.
+    pub(crate) fn remove_peer_file(
+        &self,
+        peer_id: NodeId,
+        kind: FileKind,
+        file_hash: FileHash,
+    ) -> Result<(), DatabaseError> {
+        self.peer_files_tree
+            .remove(peer_file_key(peer_id, kind, file_hash))?;
+
+        if let Some(mut client_info) = self.get_client_info(peer_id)? {
+            if client_info.shared_files.remove(&file_hash) {
+                self.clients_tree
+                    .insert(peer_id.to_be_bytes(), encode(&client_info)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the files of type `kind` shared by `peer_id`
+    pub(crate) fn get_peer_files(
+        &self,
+        peer_id: NodeId,
+        kind: FileKind,
+    ) -> Result<Vec<FileHash>, DatabaseError> {
+        self.peer_files_tree
+            .scan_prefix([peer_id, kind as u8])
+            .map(|entry| {
+                let (key, _) = entry?;
+                Ok(FileHash::from_be_bytes([key[2], key[3]]))
+            })
+            .collect()
+    }
+
+    pub(crate) fn get_client_info(&self, id: NodeId) -> Result<Option<ClientInfo>, DatabaseError> {
+        match self.clients_tree.get(id.to_be_bytes())? {
+            Some(data) => Ok(Some(decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
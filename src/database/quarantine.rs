@@ -0,0 +1,149 @@
+use packet_forge::FileMetadata;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wg_internal::network::NodeId;
+
+use super::digest::Digestible;
+use super::schema::{decode, encode};
+use super::{Database, FileKind};
+use crate::error::DatabaseError;
+
+/// A file kept aside with the moment it was quarantined
+#[derive(Debug, Serialize, Deserialize)]
+struct QuarantinedFile {
+    file_metadata: FileMetadata,
+    since: u64, // Seconds since the UNIX epoch
+}
+
+/// Seconds since the UNIX epoch, the quarantine is kept across restarts
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Key of the `quarantine_tree`: `[client_id][kind][digest BE]`, so the files of a client share a prefix.
+/// The digest ignores the id, the same file submitted again with another `FileHash` has the same key.
+fn quarantine_key(
+    client_id: NodeId,
+    file_metadata: &FileMetadata,
+) -> Result<Vec<u8>, DatabaseError> {
+    let (kind, digest) = match file_metadata {
+        FileMetadata::Song(metadata) => (FileKind::Song, metadata.digest()?),
+        FileMetadata::Video(metadata) => (FileKind::Video, metadata.digest()?),
+    };
+    let mut key = vec![client_id, kind as u8];
+    key.extend_from_slice(&digest.to_be_bytes());
+    Ok(key)
+}
+
+impl Database {
+    /// Keep aside a file submitted by `client_id` that failed validation. Quarantined files are never listed.
+    pub(crate) fn quarantine_file(
+        &self,
+        client_id: NodeId,
+        file_metadata: &FileMetadata,
+        now: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let quarantined = QuarantinedFile {
+            file_metadata: file_metadata.clone(),
+            since: unix_secs(now),
+        };
+        self.quarantine_tree.insert(
+            quarantine_key(client_id, file_metadata)?,
+            encode(&quarantined)?,
+        )?;
+        Ok(())
+    }
+
+    /// Take `file_metadata` out of the quarantine of `client_id`, whatever `FileHash` it was submitted with.
+    /// Returns true if the file was quarantined.
+    pub(crate) fn release_quarantined(
+        &self,
+        client_id: NodeId,
+        file_metadata: &FileMetadata,
+    ) -> Result<bool, DatabaseError> {
+        let removed = self
+            .quarantine_tree
+            .remove(quarantine_key(client_id, file_metadata)?)?;
+        Ok(removed.is_some())
+    }
+
+    /// Drop the files quarantined before `deadline`.
+    /// Returns the dropped files with their client and when the oldest remaining file was quarantined.
+    pub(crate) fn expire_quarantine(
+        &self,
+        deadline: SystemTime,
+    ) -> Result<(Vec<(NodeId, FileMetadata)>, Option<SystemTime>), DatabaseError> {
+        let deadline = unix_secs(deadline);
+        let mut expired = Vec::new();
+        let mut oldest: Option<u64> = None;
+
+        for entry in &self.quarantine_tree {
+            let (key, data) = entry?;
+            let quarantined: QuarantinedFile = decode(&data)?;
+            if quarantined.since < deadline {
+                self.quarantine_tree.remove(&key)?;
+                expired.push((key[0], quarantined.file_metadata));
+            } else {
+                oldest =
+                    Some(oldest.map_or(quarantined.since, |oldest| oldest.min(quarantined.since)));
+            }
+        }
+
+        let oldest = oldest.map(|since| UNIX_EPOCH + Duration::from_secs(since));
+        Ok((expired, oldest))
+    }
+
+    /// Drop every file quarantined for `client_id`, returns how many were removed.
+    pub(crate) fn clear_quarantine(&self, client_id: NodeId) -> Result<usize, DatabaseError> {
+        let mut removed = 0;
+        for key in self.quarantine_tree.scan_prefix([client_id]).keys() {
+            self.quarantine_tree.remove(key?)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseLocation;
+    use crate::test_fixtures;
+    use packet_forge::FileHash;
+
+    fn song(id: FileHash) -> FileMetadata {
+        FileMetadata::Song(test_fixtures::song(id, "title"))
+    }
+
+    #[test]
+    fn resubmitted_file_is_released() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database
+            .quarantine_file(2, &song(7), SystemTime::now())
+            .unwrap();
+
+        // Same file with the right id
+        assert!(!database.release_quarantined(3, &song(8)).unwrap());
+        assert!(database.release_quarantined(2, &song(8)).unwrap());
+        assert!(!database.release_quarantined(2, &song(7)).unwrap());
+    }
+
+    #[test]
+    fn expires_old_files_only() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        database.quarantine_file(2, &song(7), start).unwrap();
+        database
+            .quarantine_file(3, &song(7), start + Duration::from_secs(60))
+            .unwrap();
+
+        let (expired, oldest) = database
+            .expire_quarantine(start + Duration::from_secs(30))
+            .unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, 2);
+        assert_eq!(oldest, Some(start + Duration::from_secs(60)));
+        assert_eq!(database.clear_quarantine(3).unwrap(), 1);
+    }
+}
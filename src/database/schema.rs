@@ -0,0 +1,293 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::collections::HashSet;
+
+use packet_forge::{ClientType, FileHash, SongMetaData, VideoMetaData};
+use wg_internal::network::NodeId;
+
+use super::digest::Digestible;
+use super::keys::{decode_entry_key, PayloadKey};
+use super::search::Searchable;
+use super::{Database, FileEntry};
+use crate::error::DatabaseError;
+
+/// Version of the layout of the stored data.
+/// Increase it when the format of a stored value changes and register the matching step in `MIGRATIONS`.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Wrapper of every structured value stored in the trees, tagged with the schema version that wrote it.
+/// Raw payloads (song segments and video chunks) are stored as they are.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Envelope<T> {
+    pub version: u32,
+    pub value: T,
+}
+
+/// Serialize `value` inside an `Envelope` of the current `SCHEMA_VERSION`
+pub(crate) fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, DatabaseError> {
+    Ok(bincode::serialize(&Envelope {
+        version: SCHEMA_VERSION,
+        value,
+    })?)
+}
+
+/// Deserialize a value stored with `encode`.
+/// Values tagged with an older version are read as they are: the migrations rewrite every value whose layout changed.
+/// ### Error
+/// Returns `DatabaseError::UnsupportedSchema` if the value was written by a newer schema version.
+pub(crate) fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, DatabaseError> {
+    // The version is serialized first, read it alone before trusting the layout of the value
+    let version: u32 = bincode::deserialize(data)?;
+    if version > SCHEMA_VERSION {
+        return Err(DatabaseError::UnsupportedSchema {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    let envelope: Envelope<T> = bincode::deserialize(data)?;
+    Ok(envelope.value)
+}
+
+/// A step that brings the database from version `from` to `from + 1`
+struct Migration {
+    from: u32,
+    description: &'static str,
+    apply: fn(&Database) -> Result<(), DatabaseError>,
+}
+
+/// Registry of the migration steps, one for each version before `SCHEMA_VERSION`
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    description: "convert the unversioned layout to the current one",
+    apply: Database::upgrade_unversioned,
+}];
+
+/// Layout of a `FileEntry` in unversioned databases, stored as plain bincode without the digest
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyFileEntry<T> {
+    file_metadata: T,
+    peers: HashSet<NodeId>,
+}
+
+/// Parse a payload key of unversioned databases: `{prefix}:{id}`, e.g. `ts3:1234`
+fn parse_legacy_payload_key(key: &[u8]) -> Option<(&str, FileHash)> {
+    let (prefix, id) = std::str::from_utf8(key).ok()?.split_once(':')?;
+    Some((prefix, id.parse().ok()?))
+}
+
+impl Database {
+    /// Returns the schema version stored in the database, `None` if it has never been written.
+    fn get_schema_version(&self) -> Result<Option<u32>, DatabaseError> {
+        match self.db.get(SCHEMA_VERSION_KEY)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn set_schema_version(&self, version: u32) -> Result<(), DatabaseError> {
+        self.db
+            .insert(SCHEMA_VERSION_KEY, bincode::serialize(&version)?)?;
+        Ok(())
+    }
+
+    /// Bring a database written by an older server to `SCHEMA_VERSION`, one step at a time.
+    /// The stored version is updated after each step, so an interrupted migration resumes from the last completed one.
+    /// ### Error
+    /// Returns `DatabaseError::UnsupportedSchema` if the database was written by a newer server
+    /// or `DatabaseError::MissingMigration` if no step upgrades the stored version.
+    pub(crate) fn migrate(&self) -> Result<(), DatabaseError> {
+        let mut version = self.get_schema_version()?.unwrap_or(0);
+        if version > SCHEMA_VERSION {
+            return Err(DatabaseError::UnsupportedSchema {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+
+        while version < SCHEMA_VERSION {
+            let step = MIGRATIONS
+                .iter()
+                .find(|step| step.from == version)
+                .ok_or(DatabaseError::MissingMigration(version))?;
+
+            (step.apply)(self).map_err(|source| DatabaseError::MigrationFailed {
+                from: version,
+                description: step.description,
+                source: Box::new(source),
+            })?;
+            version += 1;
+            self.set_schema_version(version)?;
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Version 0 -> 1: unversioned databases store raw bincode values and keep the payloads next to the entries.
+    /// - song segments `ts{n}:{id}` move to `song_payloads_tree`
+    /// - whole video payloads `pl:{id}` are split in checksummed chunks in `video_payloads_tree`
+    /// - entries get the digest of their metadata and are indexed by peer, metadata, digest and catalog change
+    /// - each `ClientType` is replaced by a `ClientInfo`
+    ///
+    /// Collisions that happened before are not recoverable, the overwritten files are already lost.
+    fn upgrade_unversioned(&self) -> Result<(), DatabaseError> {
+        for entry in &self.songs_tree {
+            let (key, data) = entry?;
+            let Some((prefix, file)) = parse_legacy_payload_key(&key) else {
+                continue;
+            };
+            let Some(index) = prefix.strip_prefix("ts").and_then(|i| i.parse().ok()) else {
+                continue;
+            };
+            self.song_payloads_tree
+                .insert(PayloadKey::segment(file, index).encode(), data)?;
+            self.songs_tree.remove(key)?;
+        }
+
+        for entry in &self.video_tree {
+            let (key, data) = entry?;
+            let Some(("pl", file)) = parse_legacy_payload_key(&key) else {
+                continue;
+            };
+            self.insert_video_payload(file, &data)?;
+            self.video_tree.remove(key)?;
+        }
+
+        self.upgrade_legacy_entries::<SongMetaData>(&self.songs_tree)?;
+        self.upgrade_legacy_entries::<VideoMetaData>(&self.video_tree)?;
+
+        for entry in &self.clients_tree {
+            let (key, data) = entry?;
+            let client_type: ClientType = bincode::deserialize(&data)?;
+            // `insert_client` fills the shared files from the peer index
+            self.insert_client(key[0], &client_type)?;
+        }
+        Ok(())
+    }
+
+    /// Re-insert every `LegacyFileEntry` of `tree` as a `FileEntry` and index it.
+    /// Every entry counts as a catalog change, so it is listed since version 0.
+    fn upgrade_legacy_entries<T: Digestible + Searchable>(
+        &self,
+        tree: &Tree,
+    ) -> Result<(), DatabaseError> {
+        for entry in tree {
+            let (key, data) = entry?;
+            let Some(file_hash) = decode_entry_key(&key) else {
+                continue;
+            };
+            let legacy: LegacyFileEntry<T> = bincode::deserialize(&data)?;
+            for peer in &legacy.peers {
+                self.add_peer_file(*peer, T::KIND, file_hash)?;
+            }
+            self.update_search_index(file_hash, None, Some(&legacy.file_metadata))?;
+
+            let file_entry = FileEntry::new(legacy.file_metadata, legacy.peers)?;
+            tree.insert(key, encode(&file_entry)?)?;
+            self.update_digest_index(T::KIND, file_hash, None, Some(file_entry.digest))?;
+            self.record_catalog_change(T::KIND, file_hash, None)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::keys::entry_key;
+    use crate::database::{CatalogMode, DatabaseLocation, FileKind, VIDEO_CHUNK_SIZE};
+    use crate::test_fixtures::{song, video};
+
+    /// Write the values the way the unversioned server did
+    fn write_unversioned(database: &Database) {
+        database.db.remove(SCHEMA_VERSION_KEY).unwrap();
+        let song_entry = LegacyFileEntry {
+            file_metadata: song(5, "title"),
+            peers: HashSet::from([2]),
+        };
+        database
+            .songs_tree
+            .insert(entry_key(5), bincode::serialize(&song_entry).unwrap())
+            .unwrap();
+        database.songs_tree.insert(b"ts1:5", vec![3; 10]).unwrap();
+        let video_entry = LegacyFileEntry {
+            file_metadata: video(6, "title"),
+            peers: HashSet::from([1]),
+        };
+        database
+            .video_tree
+            .insert(entry_key(6), bincode::serialize(&video_entry).unwrap())
+            .unwrap();
+        database
+            .video_tree
+            .insert(b"pl:6", vec![7; VIDEO_CHUNK_SIZE + 10])
+            .unwrap();
+        database
+            .clients_tree
+            .insert([2], bincode::serialize(&ClientType::Song).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn new_database_has_current_version() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        assert_eq!(database.get_schema_version().unwrap(), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn every_version_has_a_step() {
+        for version in 0..SCHEMA_VERSION {
+            assert_eq!(
+                MIGRATIONS
+                    .iter()
+                    .filter(|step| step.from == version)
+                    .count(),
+                1,
+                "version {version}"
+            );
+        }
+    }
+
+    #[test]
+    fn migrates_unversioned_database() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        write_unversioned(&database);
+
+        database.migrate().unwrap();
+        assert_eq!(database.get_schema_version().unwrap(), Some(SCHEMA_VERSION));
+
+        let song_entry = database.get_song_entry(5).unwrap();
+        assert_eq!(song_entry.digest, song(5, "title").digest().unwrap());
+        assert_eq!(database.find_key(&song(0, "title")).unwrap(), Some(5));
+        assert!(matches!(database.get_client_type(2), Ok(ClientType::Song)));
+        assert_eq!(database.get_peer_files(2, FileKind::Song).unwrap(), vec![5]);
+
+        // The song segments move to the payload tree
+        assert_eq!(database.get_song_segment(5, 1).unwrap(), vec![3; 10]);
+        assert!(database.songs_tree.get(b"ts1:5").unwrap().is_none());
+
+        // The whole video payload is split in chunks
+        let info = database.get_video_chunks_info(6).unwrap();
+        assert_eq!(info.n_chunks, 2);
+        assert_eq!(info.total_size, VIDEO_CHUNK_SIZE as u64 + 10);
+        assert_eq!(database.get_video_chunk(6, 1, &info).unwrap(), vec![7; 10]);
+        assert!(database.video_tree.get(b"pl:6").unwrap().is_none());
+
+        // Every entry is listed by the catalog
+        assert_eq!(database.get_catalog_version().unwrap(), 2);
+    }
+
+    #[test]
+    fn fresh_init_clears_unsupported_schema() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database.set_schema_version(SCHEMA_VERSION + 1).unwrap();
+
+        assert!(matches!(
+            database.init("", None, None, CatalogMode::Persistent),
+            Err(DatabaseError::UnsupportedSchema { .. })
+        ));
+        database.init("", None, None, CatalogMode::Fresh).unwrap();
+        assert_eq!(database.get_schema_version().unwrap(), Some(SCHEMA_VERSION));
+    }
+}
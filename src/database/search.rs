@@ -0,0 +1,411 @@
+use packet_forge::{FileHash, FileQuery, SongMetaData, VideoMetaData};
+use serde::de::DeserializeOwned;
+use sled::Tree;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use wg_internal::network::NodeId;
+
+use super::keys::{decode_entry_key, entry_key};
+use super::schema::decode;
+use super::{Database, FileEntry, FileKind};
+use crate::error::DatabaseError;
+
+/// Length of the substrings indexed for the text fields. Shorter filters scan every entry.
+const TRIGRAM_LEN: usize = 3;
+/// Upper bound of the files returned in a single page
+pub(crate) const MAX_PAGE_SIZE: usize = 100;
+
+/// Field of the metadata stored in the `search_index_tree`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IndexField {
+    Title = 0,
+    Artist = 1,
+    Album = 2,
+    Duration = 3,
+    MimeType = 4,
+}
+
+/// Metadata that can be looked up with a `FileQuery`
+pub(crate) trait Searchable: DeserializeOwned {
+    const KIND: FileKind;
+    /// Text fields indexed by substring
+    const TEXT_FIELDS: &'static [IndexField];
+
+    fn text_field(&self, field: IndexField) -> Option<&str>;
+    fn duration(&self) -> u32;
+    fn mime_type(&self) -> Option<&str>;
+}
+
+impl Searchable for SongMetaData {
+    const KIND: FileKind = FileKind::Song;
+    const TEXT_FIELDS: &'static [IndexField] =
+        &[IndexField::Title, IndexField::Artist, IndexField::Album];
+
+    fn text_field(&self, field: IndexField) -> Option<&str> {
+        match field {
+            IndexField::Title => Some(&self.title),
+            IndexField::Artist => Some(&self.artist),
+            IndexField::Album => Some(&self.album),
+            _ => None,
+        }
+    }
+
+    fn duration(&self) -> u32 {
+        self.duration
+    }
+
+    fn mime_type(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Searchable for VideoMetaData {
+    const KIND: FileKind = FileKind::Video;
+    const TEXT_FIELDS: &'static [IndexField] = &[IndexField::Title];
+
+    fn text_field(&self, field: IndexField) -> Option<&str> {
+        match field {
+            IndexField::Title => Some(&self.title),
+            _ => None,
+        }
+    }
+
+    fn duration(&self) -> u32 {
+        self.duration
+    }
+
+    fn mime_type(&self) -> Option<&str> {
+        Some(&self.mime_type)
+    }
+}
+
+/// A page of files ordered by `FileHash`
+#[derive(Debug)]
+pub(crate) struct FilePage<T> {
+    pub files: Vec<T>,
+    /// Cursor of the next page, `None` if this is the last one
+    pub next_cursor: Option<FileHash>,
+    /// Entries that could not be decoded and have been left out of the page
+    pub skipped: Vec<FileHash>,
+}
+
+/// Text filters of the query, lowercase
+fn text_filters(query: &FileQuery) -> Vec<(IndexField, String)> {
+    [
+        (IndexField::Title, &query.title),
+        (IndexField::Artist, &query.artist),
+        (IndexField::Album, &query.album),
+    ]
+    .into_iter()
+    .filter_map(|(field, filter)| Some((field, filter.as_ref()?.to_lowercase())))
+    .collect()
+}
+
+/// Returns true if `metadata` satisfies every filter of `query`
+fn matches<T: Searchable>(metadata: &T, query: &FileQuery) -> bool {
+    let text_match = text_filters(query).iter().all(|(field, filter)| {
+        metadata
+            .text_field(*field)
+            .is_some_and(|value| value.to_lowercase().contains(filter.as_str()))
+    });
+
+    let duration = metadata.duration();
+    let duration_match = query.min_duration.map_or(true, |min| duration >= min)
+        && query.max_duration.map_or(true, |max| duration <= max);
+
+    let mime_match = query.mime_type.as_ref().map_or(true, |filter| {
+        metadata
+            .mime_type()
+            .is_some_and(|mime| mime.eq_ignore_ascii_case(filter))
+    });
+
+    text_match && duration_match && mime_match
+}
+
+/// Key of the `search_index_tree`: `[kind][field][value][file_hash BE]`
+fn index_key(kind: FileKind, field: IndexField, value: &[u8], id: FileHash) -> Vec<u8> {
+    let mut key = vec![kind as u8, field as u8];
+    key.extend_from_slice(value);
+    key.extend_from_slice(&entry_key(id));
+    key
+}
+
+/// Every index key of `metadata`
+fn index_keys<T: Searchable>(id: FileHash, metadata: &T) -> BTreeSet<Vec<u8>> {
+    let mut keys = BTreeSet::new();
+
+    for field in T::TEXT_FIELDS {
+        let Some(value) = metadata.text_field(*field) else {
+            continue;
+        };
+        for trigram in value.to_lowercase().as_bytes().windows(TRIGRAM_LEN) {
+            keys.insert(index_key(T::KIND, *field, trigram, id));
+        }
+    }
+
+    keys.insert(index_key(
+        T::KIND,
+        IndexField::Duration,
+        &metadata.duration().to_be_bytes(),
+        id,
+    ));
+
+    if let Some(mime) = metadata.mime_type() {
+        keys.insert(index_key(
+            T::KIND,
+            IndexField::MimeType,
+            mime.to_lowercase().as_bytes(),
+            id,
+        ));
+    }
+    keys
+}
+
+/// Extract the `FileHash` at the end of an index key
+fn indexed_file(key: &[u8]) -> Option<FileHash> {
+    decode_entry_key(key.get(key.len().checked_sub(2)?..)?)
+}
+
+impl Database {
+    /// Replace the index keys of `previous` with the ones of `current`, only the differences are written.
+    pub(crate) fn update_search_index<T: Searchable>(
+        &self,
+        id: FileHash,
+        previous: Option<&T>,
+        current: Option<&T>,
+    ) -> Result<(), DatabaseError> {
+        let old_keys = previous.map_or_else(BTreeSet::new, |metadata| index_keys(id, metadata));
+        let new_keys = current.map_or_else(BTreeSet::new, |metadata| index_keys(id, metadata));
+
+        for key in old_keys.difference(&new_keys) {
+            self.search_index_tree.remove(key)?;
+        }
+        for key in new_keys.difference(&old_keys) {
+            self.search_index_tree.insert(key, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Files of the index keys starting with `prefix`
+    fn scan_index(&self, prefix: &[u8]) -> Result<BTreeSet<FileHash>, DatabaseError> {
+        let mut files = BTreeSet::new();
+        for entry in self.search_index_tree.scan_prefix(prefix) {
+            let (key, _) = entry?;
+            files.extend(indexed_file(&key));
+        }
+        Ok(files)
+    }
+
+    /// Files that may match `query` according to the indexes, `None` if no filter can use an index.
+    /// The candidates still have to be checked against the query.
+    fn search_candidates(
+        &self,
+        kind: FileKind,
+        query: &FileQuery,
+    ) -> Result<Option<BTreeSet<FileHash>>, DatabaseError> {
+        let mut candidates: Option<BTreeSet<FileHash>> = None;
+        let mut narrow = |files: BTreeSet<FileHash>| {
+            candidates = Some(match candidates.take() {
+                Some(current) => current.intersection(&files).copied().collect(),
+                None => files,
+            });
+        };
+
+        for (field, filter) in text_filters(query) {
+            for trigram in filter.as_bytes().windows(TRIGRAM_LEN) {
+                let mut prefix = vec![kind as u8, field as u8];
+                prefix.extend_from_slice(trigram);
+                narrow(self.scan_index(&prefix)?);
+            }
+        }
+
+        if query.min_duration.is_some() || query.max_duration.is_some() {
+            let min = query.min_duration.unwrap_or(0).to_be_bytes();
+            let max = query.max_duration.unwrap_or(u32::MAX).to_be_bytes();
+            let start = index_key(kind, IndexField::Duration, &min, 0);
+            let end = index_key(kind, IndexField::Duration, &max, FileHash::MAX);
+
+            let mut files = BTreeSet::new();
+            for entry in self.search_index_tree.range(start..=end) {
+                let (key, _) = entry?;
+                files.extend(indexed_file(&key));
+            }
+            narrow(files);
+        }
+
+        if let Some(mime) = &query.mime_type {
+            let mut prefix = vec![kind as u8, IndexField::MimeType as u8];
+            prefix.extend_from_slice(mime.to_lowercase().as_bytes());
+            narrow(self.scan_index(&prefix)?);
+        }
+
+        Ok(candidates)
+    }
+
+    /// Returns the files of `tree` matching `query` ordered by `FileHash`, starting after `cursor`.
+    /// Without a query every file is returned. Files shared by `requester` are excluded.
+    fn list_page<T: Searchable>(
+        &self,
+        tree: &Tree,
+        query: Option<&FileQuery>,
+        requester: NodeId,
+        cursor: Option<FileHash>,
+        limit: usize,
+    ) -> Result<FilePage<T>, DatabaseError> {
+        let candidates = match query {
+            Some(query) => self.search_candidates(T::KIND, query)?,
+            None => None,
+        };
+
+        let ids: Box<dyn Iterator<Item = Result<FileHash, DatabaseError>>> = match candidates {
+            Some(candidates) => {
+                let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+                let ids: Vec<FileHash> = candidates
+                    .range((start, Bound::Unbounded))
+                    .copied()
+                    .collect();
+                Box::new(ids.into_iter().map(Ok))
+            }
+            // No index can be used, walk the entries in order
+            None => {
+                let start = cursor.map_or(Bound::Unbounded, |id| Bound::Excluded(entry_key(id)));
+                Box::new(
+                    tree.range((start, Bound::Unbounded))
+                        .keys()
+                        .filter_map(|key| match key {
+                            Ok(key) => decode_entry_key(&key).map(Ok),
+                            Err(e) => Some(Err(e.into())),
+                        }),
+                )
+            }
+        };
+
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        let mut last = None;
+        for id in ids {
+            let id = id?;
+            let Some(data) = tree.get(entry_key(id))? else {
+                continue;
+            };
+            // A corrupted entry must not hide the rest of the catalog
+            let Ok(entry) = decode::<FileEntry<T>>(&data) else {
+                skipped.push(id);
+                continue;
+            };
+            if entry.peers.contains(&requester)
+                || query.is_some_and(|query| !matches(&entry.file_metadata, query))
+            {
+                continue;
+            }
+
+            if files.len() == limit {
+                // There is at least another file
+                return Ok(FilePage {
+                    files,
+                    next_cursor: last,
+                    skipped,
+                });
+            }
+            files.push(entry.file_metadata);
+            last = Some(id);
+        }
+
+        Ok(FilePage {
+            files,
+            next_cursor: None,
+            skipped,
+        })
+    }
+
+    /// Search the songs matching `query`, see `FileQuery` for the supported filters.
+    pub(crate) fn search_songs(
+        &self,
+        query: &FileQuery,
+        requester: NodeId,
+        cursor: Option<FileHash>,
+        limit: usize,
+    ) -> Result<FilePage<SongMetaData>, DatabaseError> {
+        self.list_page(&self.songs_tree, Some(query), requester, cursor, limit)
+    }
+
+    /// Search the videos matching `query`, see `FileQuery` for the supported filters.
+    pub(crate) fn search_videos(
+        &self,
+        query: &FileQuery,
+        requester: NodeId,
+        cursor: Option<FileHash>,
+        limit: usize,
+    ) -> Result<FilePage<VideoMetaData>, DatabaseError> {
+        self.list_page(&self.video_tree, Some(query), requester, cursor, limit)
+    }
+
+    /// Returns a page of the songs not shared by `requester`.
+    pub(crate) fn get_songs_page(
+        &self,
+        requester: NodeId,
+        cursor: Option<FileHash>,
+        limit: usize,
+    ) -> Result<FilePage<SongMetaData>, DatabaseError> {
+        self.list_page(&self.songs_tree, None, requester, cursor, limit)
+    }
+
+    /// Returns a page of the videos not shared by `requester`.
+    pub(crate) fn get_videos_page(
+        &self,
+        requester: NodeId,
+        cursor: Option<FileHash>,
+        limit: usize,
+    ) -> Result<FilePage<VideoMetaData>, DatabaseError> {
+        self.list_page(&self.video_tree, None, requester, cursor, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseLocation;
+    use crate::test_fixtures::song;
+
+    #[test]
+    fn pages_files_by_cursor() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        for id in 1..=3 {
+            database
+                .insert_song_peer(&song(id, &format!("song {id}")), 2)
+                .unwrap();
+        }
+
+        let first = database.get_songs_page(3, None, 2).unwrap();
+        let ids: Vec<FileHash> = first.files.iter().map(|song| song.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(first.next_cursor, Some(2));
+
+        let last = database.get_songs_page(3, first.next_cursor, 2).unwrap();
+        assert_eq!(last.files.len(), 1);
+        assert_eq!(last.next_cursor, None);
+
+        // The requester does not get its own files
+        assert!(database
+            .get_songs_page(2, None, 2)
+            .unwrap()
+            .files
+            .is_empty());
+    }
+
+    #[test]
+    fn skips_undecodable_entries() {
+        let database = Database::open(&DatabaseLocation::Temporary, 1).unwrap();
+        database.insert_song_peer(&song(1, "first"), 2).unwrap();
+        database.insert_song_peer(&song(2, "second"), 2).unwrap();
+        database
+            .songs_tree
+            .insert(entry_key(1), b"corrupted".to_vec())
+            .unwrap();
+
+        let page = database.get_songs_page(3, None, 10).unwrap();
+        assert_eq!(page.files.len(), 1);
+        assert_eq!(page.files[0].id, 2);
+        assert_eq!(page.skipped, vec![1]);
+    }
+}
@@ -0,0 +1,234 @@
+use crossbeam::channel::SendError;
+use packet_forge::{FileHash, SessionIdT};
+use std::error::Error;
+use std::{fmt, io};
+use wg_internal::controller::DroneEvent;
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// Errors returned by the `Database`
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The underlying sled storage failed
+    Sled(sled::Error),
+    /// A value could not be (de)serialized with bincode
+    Bincode(bincode::Error),
+    /// A JSON metadata file could not be parsed
+    Json(serde_json::Error),
+    /// A local file could not be read
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    /// A JSON metadata file does not have the expected structure
+    InvalidJson(String),
+    /// A local song segment has an unexpected file name
+    InvalidSegmentName(String),
+    SongNotFound(FileHash),
+    VideoNotFound(FileHash),
+    ClientNotFound(NodeId),
+    /// The node is not one of the peers sharing the file
+    PeerNotFound {
+        file: FileHash,
+        peer: NodeId,
+    },
+    /// No payload is stored for the file
+    PayloadNotFound(FileHash),
+    /// The requested chunk is not part of the stored payload
+    ChunkNotFound {
+        file: FileHash,
+        index: u32,
+    },
+    /// The stored chunk does not match its checksum
+    ChecksumMismatch {
+        file: FileHash,
+        index: u32,
+    },
+    /// The payload is too big to be stored
+    PayloadTooLarge(FileHash),
+    /// Another file with different metadata is stored under the same `FileHash`
+    HashCollision(FileHash),
+    /// The database was written by a newer server
+    UnsupportedSchema {
+        found: u32,
+        supported: u32,
+    },
+    /// No migration step upgrades the database from this schema version
+    MissingMigration(u32),
+    /// A migration step could not be completed
+    MigrationFailed {
+        from: u32,
+        description: &'static str,
+        source: Box<DatabaseError>,
+    },
+    /// An operation over many entries completed with errors
+    Multiple(Vec<DatabaseError>),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sled(e) => write!(f, "Error accessing database: {e}"),
+            Self::Bincode(e) => write!(f, "Serialization error: {e}"),
+            Self::Json(e) => write!(f, "Error parsing JSON: {e}"),
+            Self::Io { path, source } => write!(f, "Error reading {path}: {source}"),
+            Self::InvalidJson(msg) => write!(f, "Invalid JSON: {msg}"),
+            Self::InvalidSegmentName(path) => write!(f, "Invalid segment file name: {path}"),
+            Self::SongNotFound(id) => write!(f, "Song {id} not found"),
+            Self::VideoNotFound(id) => write!(f, "Video {id} not found"),
+            Self::ClientNotFound(id) => {
+                write!(f, "Client {id} not found. Subscribe to the server!")
+            }
+            Self::PeerNotFound { file, peer } => {
+                write!(f, "Node {peer} does not share file {file}")
+            }
+            Self::PayloadNotFound(id) => write!(f, "Payload of file {id} not found"),
+            Self::ChunkNotFound { file, index } => {
+                write!(f, "Chunk {index} of file {file} not found")
+            }
+            Self::ChecksumMismatch { file, index } => {
+                write!(f, "Checksum mismatch for chunk {index} of file {file}")
+            }
+            Self::PayloadTooLarge(id) => write!(f, "Payload of file {id} is too large"),
+            Self::HashCollision(id) => {
+                write!(f, "File hash {id} is already used by a different file")
+            }
+            Self::UnsupportedSchema { found, supported } => write!(
+                f,
+                "Unsupported database schema version {found}, latest supported is {supported}"
+            ),
+            Self::MissingMigration(version) => {
+                write!(f, "No migration available from schema version {version}")
+            }
+            Self::MigrationFailed {
+                from,
+                description,
+                source,
+            } => write!(
+                f,
+                "Migration from schema version {from} ({description}) failed: {source}"
+            ),
+            Self::Multiple(errors) => {
+                write!(f, "Completed with {} errors:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n - {error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Sled(e) => Some(e),
+            Self::Bincode(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Io { source, .. } => Some(source),
+            Self::MigrationFailed { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<sled::Error> for DatabaseError {
+    fn from(e: sled::Error) -> Self {
+        Self::Sled(e)
+    }
+}
+
+impl From<bincode::Error> for DatabaseError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Bincode(e)
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// Errors returned while the `Server` handles packets, messages and commands
+#[derive(Debug)]
+pub enum ServerError {
+    Database(DatabaseError),
+    /// No channel towards the node is available
+    NoNeighbour(NodeId),
+    /// No path towards the node is known
+    NoRoute(NodeId),
+    /// The channel towards `next_hop` is disconnected, the unsent packet is boxed to keep the error small
+    PacketSend {
+        next_hop: NodeId,
+        source: Box<SendError<Packet>>,
+    },
+    /// The channel towards the Simulation Controller is disconnected
+    ControllerSend(Box<SendError<DroneEvent>>),
+    /// A message could not be split into packets
+    Disassemble(String),
+    /// The session is larger than the memory budget of the sent fragments
+    HistoryBudgetExceeded(SessionIdT),
+    /// The received `FileHash` does not match the one calculated from the metadata
+    HashMismatch {
+        received: FileHash,
+        calculated: FileHash,
+    },
+    /// No sender for the node is registered
+    SenderNotFound(NodeId),
+    /// A sender for the node is already registered
+    SenderExists(NodeId),
+    UnhandledCommand(&'static str),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "{e}"),
+            Self::NoNeighbour(id) => write!(f, "No neigbour of ID [{id}] found."),
+            Self::NoRoute(id) => write!(f, "No path towards node [{id}] found."),
+            Self::PacketSend { next_hop, source } => write!(
+                f,
+                "Failed to send packet to [DRONE-{next_hop}].\n {} \n Error: {source}",
+                source.0
+            ),
+            Self::ControllerSend(e) => write!(
+                f,
+                "Error occurred while sending packet event to SC. Error: {e}"
+            ),
+            Self::Disassemble(msg) => write!(f, "Error while disassembling message: {msg}"),
+            Self::HistoryBudgetExceeded(id) => {
+                write!(f, "Session {id} does not fit in the sent fragments budget")
+            }
+            Self::HashMismatch {
+                received,
+                calculated,
+            } => write!(
+                f,
+                "File hash mismatch! Received: [ {received:?} ]\nCalculated: [ {calculated:?} ]"
+            ),
+            Self::SenderNotFound(id) => write!(f, "Sender with id {id} not found"),
+            Self::SenderExists(id) => write!(f, "Sender with id {id} already exists"),
+            Self::UnhandledCommand(command) => {
+                write!(f, "Received unhandled SC command ({command})!")
+            }
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Database(e) => Some(e),
+            Self::PacketSend { source, .. } => Some(source.as_ref()),
+            Self::ControllerSend(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatabaseError> for ServerError {
+    fn from(e: DatabaseError) -> Self {
+        Self::Database(e)
+    }
+}
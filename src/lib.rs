@@ -1,6 +1,11 @@
 mod database;
+mod error;
 mod packet_send;
 mod server;
+#[cfg(test)]
+mod test_fixtures;
 mod utils;
 
+pub use database::{CatalogMode, DatabaseLocation};
+pub use error::{DatabaseError, ServerError};
 pub use server::*;
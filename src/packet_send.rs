@@ -1,3 +1,5 @@
+use crate::error::ServerError;
+
 use crossbeam::channel::Sender;
 use std::collections::HashMap;
 use wg_internal::controller::DroneEvent;
@@ -7,27 +9,28 @@ use wg_internal::packet::Packet;
 pub fn get_sender(
     node_id: NodeId,
     senders: &HashMap<NodeId, Sender<Packet>>,
-) -> Result<Sender<Packet>, String> {
+) -> Result<Sender<Packet>, ServerError> {
     if let Some(sender) = senders.get(&node_id) {
         return Ok(sender.clone());
     }
-    Err(format!("No neigbour of ID [{node_id}] found."))
+    Err(ServerError::NoNeighbour(node_id))
 }
 
-pub fn send_packet(sender: &Sender<Packet>, packet: &Packet) -> Result<(), String> {
-    match sender.send(packet.clone()) {
-        Ok(()) => Ok(()),
-        Err(err) => Err(format!(
-            "Tried sending packet: {packet} but an error occurred: {err}"
-        )),
-    }
+pub fn send_packet(
+    sender: &Sender<Packet>,
+    packet: &Packet,
+    next_hop: NodeId,
+) -> Result<(), ServerError> {
+    sender
+        .send(packet.clone())
+        .map_err(|source| ServerError::PacketSend {
+            next_hop,
+            source: Box::new(source),
+        })
 }
 
-pub fn sc_send_packet(sender: &Sender<DroneEvent>, packet: &DroneEvent) -> Result<(), String> {
-    match sender.send(packet.clone()) {
-        Ok(()) => Ok(()),
-        Err(err) => Err(format!(
-            "Error occurred while sending packet event to SC. Error: {err}"
-        )),
-    }
+pub fn sc_send_packet(sender: &Sender<DroneEvent>, packet: &DroneEvent) -> Result<(), ServerError> {
+    sender
+        .send(packet.clone())
+        .map_err(|e| ServerError::ControllerSend(Box::new(e)))
 }
@@ -1,70 +1,152 @@
+mod catalog_updates;
 mod commands_handler;
+mod events;
+mod flow_control;
+mod leases;
+mod link_costs;
 mod logger_settings;
 mod packet_dispatcher;
+mod reassembly;
+mod retransmission;
+mod scheduler;
+mod sent_history;
+mod settings;
 mod utils;
-mod video_chunker;
+mod validation;
 
-use crate::database::Database;
+use crate::database::{CatalogMode, Database, DatabaseLocation};
+use crate::error::ServerError;
+use catalog_updates::{CatalogSubscribers, DEFAULT_PUSH_INTERVAL};
+use flow_control::SendWindow;
+use leases::{ClientLeases, DEFAULT_LEASE_DURATION};
+use link_costs::LinkCosts;
+use reassembly::{ReassemblyBuffer, DEFAULT_REASSEMBLY_TIMEOUT};
+use scheduler::{Scheduler, Task};
+use sent_history::{SentHistory, DEFAULT_HISTORY_BUDGET, DEFAULT_SESSION_EXPIRY};
+use validation::DEFAULT_QUARANTINE_EXPIRY;
 
-use crossbeam::channel::{select_biased, Receiver, Sender, TryRecvError};
+use crossbeam::channel::{at, never, select_biased, Receiver, Sender};
 use logger::{LogLevel, Logger};
-use packet_forge::{PacketForge, SessionIdT};
+use packet_forge::PacketForge;
 use routing_handler::RoutingHandler;
 use std::collections::{HashMap, HashSet};
+use std::process;
 use std::time::{Duration, Instant};
 use wg_internal::controller::{DroneCommand, DroneEvent};
 use wg_internal::network::NodeId;
-use wg_internal::packet::{Fragment, Packet};
+use wg_internal::packet::Packet;
+
+pub use events::ServerEvent;
+pub use validation::ValidationPolicy;
+
+/// Default number of peers sent in a `ResponsePeerList`
+const DEFAULT_PEER_LIST_SIZE: usize = 10;
 
 pub struct Server {
     id: NodeId,
     controller_send: Sender<DroneEvent>,
     controller_recv: Receiver<DroneCommand>,
+    event_send: Sender<ServerEvent>, // *Events that no DroneEvent describes*
     packet_recv: Receiver<Packet>,
     packet_send: HashMap<NodeId, Sender<Packet>>,
     terminated: bool,
     // Handle incoming packages
     packet_forge: PacketForge,
-    recv_fragments_map: HashMap<(NodeId, SessionIdT), Vec<Fragment>>, // (client_id, session_id) -> fragment --- *Keep track of the received fragments*
+    reassembly_buffer: ReassemblyBuffer, // (client_id, session_id) -> fragment_index -> Fragment --- *Keep track of the received fragments*
     // Handle outgoing packets
-    sent_fragments_history: HashMap<(u64, SessionIdT), Packet>, // (fragment_index, session_id) -> Packet(Fragment) --- *Save the sent fragments*
+    sent_fragments_history: SentHistory, // session_id -> fragment_index -> Packet(Fragment) --- *Save the sent fragments*
+    send_windows: HashMap<NodeId, SendWindow>, // client_id -> SendWindow --- *Fragments waiting to be sent, shared by the sessions*
     // Storage data structures
     database: Database,
+    catalog_mode: CatalogMode,
+    validation_policy: ValidationPolicy,
+    quarantine_expiry: Duration, // Time after which a quarantined file is dropped
+    catalog_subscribers: CatalogSubscribers, // client_id -> catalog version --- *Versions known by the clients*
+    client_leases: ClientLeases, // client_id -> last renewal --- *Subscriptions of the live clients*
+    peer_list_size: usize,       // Max peers sent in a ResponsePeerList
     // Network graph
     routing_handler: RoutingHandler,
+    link_costs: LinkCosts, // node_id -> congestion and nacks --- *Compare the paths of the RoutingHandler*
     curr_flood_id: u64,
     used_flood_id: HashSet<u64>,
     flood_countdown: Instant, // Initialize timer
+    // Timed tasks
+    scheduler: Scheduler,
     // Logger
     logger: Logger,
 }
 
 impl Server {
+    /// Creates a server with its database in `db/server-{id}`.
+    /// The `ServerEvent`s, the events that no `DroneEvent` describes, are sent to the Simulation Controller on `event_send`.
+    /// The process exits if the database cannot be opened, use `try_new` to handle the error.
     #[must_use]
     pub fn new(
         id: NodeId,
         command_send: Sender<DroneEvent>,
         command_recv: Receiver<DroneCommand>,
+        event_send: Sender<ServerEvent>,
         receiver: Receiver<Packet>,
         senders: HashMap<NodeId, Sender<Packet>>,
     ) -> Self {
-        Server {
+        let location = DatabaseLocation::Path(format!("db/server-{id}"));
+        Self::try_new(
+            id,
+            command_send,
+            command_recv,
+            event_send,
+            receiver,
+            senders,
+            &location,
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Error opening database: {e}");
+            process::exit(1); // Exit the program with an error code
+        })
+    }
+
+    /// Creates a server with its database at `database`.
+    /// ### Error
+    /// Returns `ServerError::Database` if the database cannot be opened.
+    pub fn try_new(
+        id: NodeId,
+        command_send: Sender<DroneEvent>,
+        command_recv: Receiver<DroneCommand>,
+        event_send: Sender<ServerEvent>,
+        receiver: Receiver<Packet>,
+        senders: HashMap<NodeId, Sender<Packet>>,
+        database: &DatabaseLocation,
+    ) -> Result<Self, ServerError> {
+        Ok(Server {
             id,
             controller_send: command_send,
             controller_recv: command_recv,
+            event_send,
             packet_recv: receiver,
             packet_send: senders,
             terminated: false,
             packet_forge: PacketForge::new(),
-            recv_fragments_map: HashMap::new(),
-            sent_fragments_history: HashMap::new(),
-            database: Database::new(&format!("db/server-{id}"), id),
+            reassembly_buffer: ReassemblyBuffer::new(DEFAULT_REASSEMBLY_TIMEOUT),
+            sent_fragments_history: SentHistory::new(
+                DEFAULT_HISTORY_BUDGET,
+                DEFAULT_SESSION_EXPIRY,
+            ),
+            send_windows: HashMap::new(),
+            database: Database::open(database, id)?,
+            catalog_mode: CatalogMode::Fresh,
+            validation_policy: ValidationPolicy::default(),
+            quarantine_expiry: DEFAULT_QUARANTINE_EXPIRY,
+            catalog_subscribers: CatalogSubscribers::new(DEFAULT_PUSH_INTERVAL),
+            client_leases: ClientLeases::new(DEFAULT_LEASE_DURATION),
+            peer_list_size: DEFAULT_PEER_LIST_SIZE,
             routing_handler: RoutingHandler::new(),
+            link_costs: LinkCosts::new(),
             curr_flood_id: 0,
             used_flood_id: HashSet::new(),
             flood_countdown: Instant::now(),
+            scheduler: Scheduler::new(),
             logger: Logger::new(LogLevel::None as u8, false, format!("SERVER-{id}")),
-        }
+        })
     }
 
     #[must_use]
@@ -72,48 +154,73 @@ impl Server {
         self.id
     }
 
+    /// Returns the bytes held by the fragments waiting for an `Ack`
+    #[must_use]
+    pub fn sent_history_memory(&self) -> usize {
+        self.sent_fragments_history.memory_usage()
+    }
+
     pub fn run(&mut self, db_path: &str) {
         // Init database
-        let res = self
-            .database
-            .init(db_path, Some("init_songs.json"), Some("init_videos.json"));
+        let res = self.database.init(
+            db_path,
+            Some("init_songs.json"),
+            Some("init_videos.json"),
+            self.catalog_mode,
+        );
 
-        if let Err(msg) = res {
-            self.logger.log_error(&msg);
+        if let Err(err) = res {
+            self.logger.log_error(&err.to_string());
             return;
         }
         self.logger.log_debug("Database successfully initiated!");
 
+        // Clients kept by a persistent catalog must show up again before their lease expires
+        match self.database.get_client_ids() {
+            Ok(clients) => {
+                for client_id in clients {
+                    self.grant_lease(client_id);
+                }
+            }
+            Err(err) => self.logger.log_error(&err.to_string()),
+        }
+        // A persistent catalog may hold files quarantined before the restart
+        self.scheduler
+            .schedule(Task::QuarantineExpiry, Instant::now());
+
         // At start perform the first flood_request
         self.init_flood_request();
+        self.scheduler.schedule(
+            Task::Flooding,
+            self.flood_countdown + scheduler::FLOOD_INTERVAL,
+        );
 
-        loop {
-            if self.terminated {
-                break;
-            }
-
-            // Flood request every 1 minute
-            if self.flood_countdown.elapsed() >= Duration::from_secs(60) {
-                self.init_flood_request();
-            }
+        while !self.terminated {
+            // Wake up when the next timed task is due, sleep until a message arrives otherwise
+            let timer = self.scheduler.next_deadline().map_or_else(never, at);
 
-            match self.controller_recv.try_recv() {
-                Ok(command) => self.command_dispatcher(&command),
-                Err(TryRecvError::Empty) => {}
-                Err(e) => {
-                    self.logger
-                        .log_error(&format!("Error receiving command: {e}"));
-                }
+            select_biased! {
+                recv(self.controller_recv) -> command => match command {
+                    Ok(command) => self.command_dispatcher(&command),
+                    Err(e) => {
+                        self.logger
+                            .log_error(&format!("Error receiving command: {e}. Terminating!"));
+                        self.terminated = true;
+                    }
+                },
+                recv(self.packet_recv) -> packet => match packet {
+                    Ok(packet) => self.packet_dispatcher(&packet),
+                    Err(e) => {
+                        self.logger
+                            .log_error(&format!("Error receiving message: {e}. Terminating!"));
+                        self.terminated = true;
+                    }
+                },
+                recv(timer) -> _ => {}
             }
 
-            match self.packet_recv.try_recv() {
-                Ok(packet) => self.packet_dispatcher(&packet),
-                Err(TryRecvError::Empty) => {}
-                Err(e) => {
-                    self.logger
-                        .log_error(&format!("Error receiving message: {e}"));
-                }
-            }
+            // Run timed tasks even under constant traffic
+            self.run_scheduled_tasks();
         }
     }
 }
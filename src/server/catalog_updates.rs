@@ -0,0 +1,152 @@
+use super::Server;
+use crate::database::{FileKind, MAX_PAGE_SIZE};
+use crate::server::scheduler::Task;
+
+use packet_forge::ResponseFileListChanges;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+
+/// Default minimum interval between two rounds of file list updates
+pub(crate) const DEFAULT_PUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps track of the catalog version known by each subscribed client.
+/// Changes are pushed in rounds at most once per interval, so the ones made in between are batched.
+#[derive(Debug)]
+pub(crate) struct CatalogSubscribers {
+    seen: HashMap<NodeId, u64>,
+    interval: Duration,
+    last_push: Option<Instant>,
+}
+
+impl CatalogSubscribers {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            seen: HashMap::new(),
+            interval,
+            last_push: None,
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Record that `client` knows the catalog up to `version`. The known version never decreases.
+    pub fn mark_seen(&mut self, client: NodeId, version: u64) {
+        self.seen
+            .entry(client)
+            .and_modify(|seen| *seen = (*seen).max(version))
+            .or_insert(version);
+    }
+
+    /// Stop sending updates to `client`
+    pub fn remove(&mut self, client: NodeId) {
+        self.seen.remove(&client);
+    }
+
+    /// Every tracked client with the catalog version it knows
+    pub fn seen(&self) -> Vec<(NodeId, u64)> {
+        self.seen
+            .iter()
+            .map(|(client, version)| (*client, *version))
+            .collect()
+    }
+
+    /// Earliest instant at which the next round can run
+    pub fn next_push(&self, now: Instant) -> Instant {
+        self.last_push
+            .map_or(now, |last| (last + self.interval).max(now))
+    }
+
+    pub fn set_pushed(&mut self, now: Instant) {
+        self.last_push = Some(now);
+    }
+}
+
+impl Server {
+    /// Schedule a round of file list updates after the catalog changed.
+    pub(crate) fn schedule_catalog_push(&mut self) {
+        let deadline = self.catalog_subscribers.next_push(Instant::now());
+        self.scheduler
+            .schedule_no_later(Task::CatalogPush, deadline);
+    }
+
+    /// Send to every tracked client the changes of its file type after the version it knows.
+    /// At most `MAX_PAGE_SIZE` changes are sent to a client per round, another round is scheduled for the rest.
+    /// Clients are tracked once they received a file list page or a list of changes.
+    pub(crate) fn push_catalog_changes(&mut self) {
+        let now = Instant::now();
+        self.catalog_subscribers.set_pushed(now);
+
+        let current_version = match self.database.get_catalog_version() {
+            Ok(version) => version,
+            Err(err) => {
+                self.logger.log_error(&format!("[CATALOG PUSH] - {err}"));
+                return;
+            }
+        };
+
+        let mut pending = false;
+        for (client_id, since) in self.catalog_subscribers.seen() {
+            if since >= current_version {
+                continue;
+            }
+
+            let changes = self
+                .database
+                .get_client_type(client_id)
+                .and_then(|client_type| {
+                    self.database.get_catalog_changes(
+                        FileKind::from(&client_type),
+                        since,
+                        client_id,
+                        MAX_PAGE_SIZE,
+                    )
+                });
+
+            let changes = match changes {
+                Ok(changes) => changes,
+                Err(err) => {
+                    self.logger
+                        .log_error(&format!("[CATALOG PUSH] - [CLIENT-{client_id}] {err}"));
+                    continue;
+                }
+            };
+
+            if changes.version < current_version {
+                pending = true;
+            }
+
+            // Only files of the other type or shared by the client itself changed
+            if changes.changes.is_empty() {
+                self.catalog_subscribers
+                    .mark_seen(client_id, changes.version);
+                continue;
+            }
+
+            self.logger.log_debug(&format!(
+                "[CATALOG PUSH] - {} changes for [CLIENT-{client_id}] from version {since} to {}",
+                changes.changes.len(),
+                changes.version
+            ));
+            let version = changes.version;
+            let response = ResponseFileListChanges::new(self.id, changes.changes, version);
+
+            // Without a path the changes are sent in the next round
+            if let Err(err) = self.send_message(client_id, response, None, "CATALOG PUSH") {
+                self.logger
+                    .log_error(&format!("[CATALOG PUSH] - [CLIENT-{client_id}] {err}"));
+                pending = true;
+                continue;
+            }
+
+            self.catalog_subscribers.mark_seen(client_id, version);
+        }
+
+        if pending {
+            let deadline = self.catalog_subscribers.next_push(now);
+            self.scheduler.schedule(Task::CatalogPush, deadline);
+        }
+    }
+}
@@ -1,23 +1,28 @@
 use super::Server;
+use crate::error::ServerError;
 
 use crossbeam::channel::Sender;
 use wg_internal::{controller::DroneCommand, network::NodeId, packet::Packet};
 
 impl Server {
-    pub(crate) fn remove_sender(&mut self, id: NodeId) -> Result<(), String> {
+    pub(crate) fn remove_sender(&mut self, id: NodeId) -> Result<(), ServerError> {
         let res = self.packet_send.remove(&id);
         if res.is_none() {
-            return Err(format!("[REMOVE SENDER] - Sender with id {id} not found"));
+            return Err(ServerError::SenderNotFound(id));
         }
         self.logger
             .log_info(&format!("[REMOVE SENDER] - Sender with id {id} removed"));
         Ok(())
     }
 
-    pub(crate) fn add_sender(&mut self, id: NodeId, sender: &Sender<Packet>) -> Result<(), String> {
+    pub(crate) fn add_sender(
+        &mut self,
+        id: NodeId,
+        sender: &Sender<Packet>,
+    ) -> Result<(), ServerError> {
         let res = self.packet_send.insert(id, sender.clone());
         if res.is_some() {
-            return Err(format!("[ADD SENDER] - Sender with id {id} already exists",));
+            return Err(ServerError::SenderExists(id));
         }
         self.logger
             .log_info(&format!("[ADD SENDER] - Sender with id {id} added"));
@@ -42,12 +47,12 @@ impl Server {
                     Ok(())
                 }
                 DroneCommand::SetPacketDropRate(_) => {
-                    Err("[SC COMMAND] - Received unhandled SC command (ChangePdr)!".to_string())
+                    Err(ServerError::UnhandledCommand("ChangePdr"))
                 }
             };
 
             if let Err(err) = res {
-                self.logger.log_error(&err);
+                self.logger.log_error(&format!("[SC COMMAND] - {err}"));
             }
         }
     }
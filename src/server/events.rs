@@ -0,0 +1,44 @@
+use super::Server;
+
+use packet_forge::SessionIdT;
+use wg_internal::network::NodeId;
+
+/// Events of the server the Simulation Controller may want to know about.
+/// `DroneEvent`s only describe packets moving through the network (`PacketSent`, `PacketDropped`,
+/// `ControllerShortcut`), so every other event is sent on the event channel given to `Server::new`.
+/// `DroneEvent::PacketDropped` is never used by the server for them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// A received packet or message has been discarded because it breaks the protocol.
+    /// The source is `None` when the routing header does not name one.
+    ProtocolViolation {
+        source: Option<NodeId>,
+        session_id: SessionIdT,
+        reason: String,
+    },
+    /// The handler of a message panicked, the message has been discarded
+    HandlerPanicked {
+        source: NodeId,
+        session_id: SessionIdT,
+        reason: String,
+    },
+    /// The client did not renew its lease in time and has been unsubscribed
+    ClientExpired(NodeId),
+    /// A sent session has been given up after too many retransmissions of one of its fragments
+    SessionAbandoned {
+        destination: NodeId,
+        session_id: SessionIdT,
+    },
+}
+
+impl Server {
+    /// Log `event` and send it to the Simulation Controller.
+    pub(crate) fn report_event(&self, event: ServerEvent) {
+        self.logger.log_warn(&format!("[SERVER EVENT] - {event:?}"));
+
+        if let Err(err) = self.event_send.send(event) {
+            self.logger
+                .log_error(&format!("[SERVER EVENT] - Event forward: {err}"));
+        }
+    }
+}
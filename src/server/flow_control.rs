@@ -0,0 +1,235 @@
+use super::Server;
+use crate::error::ServerError;
+
+use packet_forge::SessionIdT;
+use std::collections::{HashMap, VecDeque};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// Fragments sent before waiting for the first `Ack`
+pub(crate) const INITIAL_WINDOW: usize = 8;
+/// Upper bound of the congestion window
+pub(crate) const MAX_WINDOW: usize = 256;
+/// Window size after which growth switches from exponential to linear
+pub(crate) const INITIAL_SLOW_START_THRESHOLD: usize = 64;
+
+/// Outgoing fragments towards one client, shared by every session sent to it so that
+/// concurrent sessions do not multiply the load on the path.
+/// Fragments are released only as the previous ones are acknowledged.
+/// The congestion window grows on each `Ack` (exponentially up to the threshold, linearly afterwards)
+/// and is halved on each `Nack(Dropped)`.
+#[derive(Debug)]
+pub(crate) struct SendWindow {
+    pending: VecDeque<(Packet, NodeId)>, // (fragment, next_hop) in sending order
+    in_flight: HashMap<SessionIdT, usize>, // session_id -> fragments released and not acknowledged
+    congestion_window: usize,
+    slow_start_threshold: usize,
+    acked_in_window: usize,
+}
+
+impl Default for SendWindow {
+    fn default() -> Self {
+        SendWindow {
+            pending: VecDeque::new(),
+            in_flight: HashMap::new(),
+            congestion_window: INITIAL_WINDOW,
+            slow_start_threshold: INITIAL_SLOW_START_THRESHOLD,
+            acked_in_window: 0,
+        }
+    }
+}
+
+impl SendWindow {
+    /// Queue the fragments of a session after the ones already waiting
+    pub fn enqueue(&mut self, packets: &[Packet], next_hop: NodeId) {
+        self.pending
+            .extend(packets.iter().map(|packet| (packet.clone(), next_hop)));
+    }
+
+    /// Number of fragments waiting to be released
+    pub fn queued(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Fragments released and not acknowledged yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.values().sum()
+    }
+
+    /// Take the packets that fit in the congestion window, with their next hop
+    pub fn release(&mut self) -> Vec<(Packet, NodeId)> {
+        let available = self.congestion_window.saturating_sub(self.in_flight());
+        let n = available.min(self.pending.len());
+        let ready: Vec<(Packet, NodeId)> = self.pending.drain(..n).collect();
+        for (packet, _) in &ready {
+            *self.in_flight.entry(packet.session_id).or_default() += 1;
+        }
+        ready
+    }
+
+    pub fn on_ack(&mut self, session_id: SessionIdT) {
+        if let Some(in_flight) = self.in_flight.get_mut(&session_id) {
+            *in_flight -= 1;
+            if *in_flight == 0 {
+                self.in_flight.remove(&session_id);
+            }
+        }
+
+        if self.congestion_window < self.slow_start_threshold {
+            self.congestion_window += 1;
+        } else {
+            self.acked_in_window += 1;
+            if self.acked_in_window >= self.congestion_window {
+                self.congestion_window += 1;
+                self.acked_in_window = 0;
+            }
+        }
+        self.congestion_window = self.congestion_window.min(MAX_WINDOW);
+    }
+
+    pub fn on_drop(&mut self) {
+        self.slow_start_threshold = (self.congestion_window / 2).max(1);
+        self.congestion_window = self.slow_start_threshold;
+        self.acked_in_window = 0;
+    }
+
+    /// Forget a session that will not be acknowledged anymore, so it stops holding slots of the window
+    pub fn discard(&mut self, session_id: SessionIdT) {
+        self.pending
+            .retain(|(packet, _)| packet.session_id != session_id);
+        self.in_flight.remove(&session_id);
+    }
+}
+
+impl Server {
+    /// Client a fragment is addressed to, which identifies its `SendWindow`
+    pub(crate) fn destination(packet: &Packet) -> Option<NodeId> {
+        packet.routing_header.hops.last().copied()
+    }
+
+    /// Send the fragments released by a window and save them into `packet_history`.
+    /// Fragments are saved even if the send failed: the retransmission will look for a new route.
+    /// ### Error
+    /// Returns the last error met while sending.
+    pub(crate) fn send_released(
+        &mut self,
+        ready: Vec<(Packet, NodeId)>,
+    ) -> Result<(), ServerError> {
+        let mut result = Ok(());
+        let mut packets = Vec::with_capacity(ready.len());
+        for (packet, next_hop) in ready {
+            if let Err(err) = self.send_packets_vec(std::slice::from_ref(&packet), next_hop) {
+                result = Err(err);
+            }
+            packets.push(packet);
+        }
+
+        self.insert_packet_history(&packets);
+        result
+    }
+
+    /// Grow the window of `destination` after a fragment of `session_id` has been acknowledged
+    /// and send the fragments that now fit in it.
+    pub(crate) fn window_on_ack(&mut self, destination: NodeId, session_id: SessionIdT) {
+        let Some(window) = self.send_windows.get_mut(&destination) else {
+            return;
+        };
+
+        window.on_ack(session_id);
+        let ready = window.release();
+        if ready.is_empty() {
+            return;
+        }
+
+        if let Err(err) = self.send_released(ready) {
+            self.logger.log_error(&format!(
+                "[FLOW CONTROL] {err}\n Fragments left to the retransmission timer"
+            ));
+        }
+    }
+
+    /// Shrink the window of `destination` after a fragment has been dropped.
+    pub(crate) fn window_on_drop(&mut self, destination: NodeId) {
+        if let Some(window) = self.send_windows.get_mut(&destination) {
+            window.on_drop();
+        }
+    }
+
+    /// Stop sending the fragments of a session that has been evicted, expired or abandoned.
+    pub(crate) fn window_discard(&mut self, session_id: SessionIdT) {
+        for window in self.send_windows.values_mut() {
+            window.discard(session_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::fragment_packet;
+
+    fn packets(session_id: SessionIdT, n: u64) -> Vec<Packet> {
+        (0..n)
+            .map(|fragment_index| fragment_packet(session_id, fragment_index, n))
+            .collect()
+    }
+
+    #[test]
+    fn releases_up_to_the_window() {
+        let mut window = SendWindow::default();
+        window.enqueue(&packets(1, INITIAL_WINDOW as u64 + 2), 2);
+
+        assert_eq!(window.release().len(), INITIAL_WINDOW);
+        assert!(window.release().is_empty());
+        assert_eq!(window.queued(), 2);
+
+        // Slow start: each Ack frees a slot and grows the window by one
+        window.on_ack(1);
+        assert_eq!(window.release().len(), 2);
+        assert_eq!(window.in_flight(), INITIAL_WINDOW + 1);
+    }
+
+    #[test]
+    fn sessions_share_the_window() {
+        let mut window = SendWindow::default();
+        window.enqueue(&packets(1, INITIAL_WINDOW as u64), 2);
+        window.enqueue(&packets(2, 4), 2);
+
+        let ready = window.release();
+        assert_eq!(ready.len(), INITIAL_WINDOW);
+        assert!(ready.iter().all(|(packet, _)| packet.session_id == 1));
+        assert_eq!(window.queued(), 4);
+    }
+
+    #[test]
+    fn drop_halves_the_window() {
+        let mut window = SendWindow::default();
+        window.enqueue(&packets(1, 2 * INITIAL_WINDOW as u64), 2);
+        window.release();
+
+        // The window is halved below the fragments in flight
+        window.on_drop();
+        assert!(window.release().is_empty());
+
+        // Past the threshold the window grows by one per full window of Acks
+        for _ in 0..INITIAL_WINDOW / 2 {
+            window.on_ack(1);
+        }
+        assert_eq!(window.in_flight(), INITIAL_WINDOW / 2);
+        assert_eq!(window.release().len(), 1);
+    }
+
+    #[test]
+    fn discarded_session_frees_its_slots() {
+        let mut window = SendWindow::default();
+        window.enqueue(&packets(1, INITIAL_WINDOW as u64 + 1), 2);
+        window.enqueue(&packets(2, 1), 2);
+        window.release();
+
+        window.discard(1);
+        assert_eq!(window.in_flight(), 0);
+        let ready = window.release();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0.session_id, 2);
+    }
+}
@@ -0,0 +1,137 @@
+use super::{Server, ServerEvent};
+use crate::server::scheduler::Task;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+
+/// Default time after which a silent client is unsubscribed
+pub(crate) const DEFAULT_LEASE_DURATION: Duration = Duration::from_secs(90);
+
+/// Why a client stops being subscribed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnsubscribeReason {
+    /// The client sent `UnsubscribeClient`
+    Requested,
+    /// The client did not renew its lease in time
+    LeaseExpired,
+}
+
+/// Subscription leases of the clients, renewed by any packet they send or by a `Heartbeat`.
+#[derive(Debug)]
+pub(crate) struct ClientLeases {
+    renewed: HashMap<NodeId, Instant>, // client_id -> last renewal
+    duration: Duration,
+}
+
+impl ClientLeases {
+    pub fn new(duration: Duration) -> Self {
+        ClientLeases {
+            renewed: HashMap::new(),
+            duration,
+        }
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+
+    /// Start or renew the lease of `client`
+    pub fn grant(&mut self, client: NodeId, now: Instant) {
+        self.renewed.insert(client, now);
+    }
+
+    /// Renew the lease of `client` if it holds one. Returns false for unknown nodes.
+    pub fn refresh(&mut self, client: NodeId, now: Instant) -> bool {
+        match self.renewed.get_mut(&client) {
+            Some(renewed) => {
+                *renewed = now;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Last time `client` renewed its lease, `None` if it holds none
+    pub fn last_renewal(&self, client: NodeId) -> Option<Instant> {
+        self.renewed.get(&client).copied()
+    }
+
+    pub fn revoke(&mut self, client: NodeId) {
+        self.renewed.remove(&client);
+    }
+
+    /// Instant at which the oldest lease expires
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.renewed
+            .values()
+            .min()
+            .map(|renewed| *renewed + self.duration)
+    }
+
+    /// Remove and return the clients whose lease has not been renewed within the lease duration.
+    pub fn expire(&mut self, now: Instant) -> Vec<NodeId> {
+        let duration = self.duration;
+        let expired: Vec<NodeId> = self
+            .renewed
+            .iter()
+            .filter(|(_, renewed)| **renewed + duration <= now)
+            .map(|(client, _)| *client)
+            .collect();
+
+        for client in &expired {
+            self.renewed.remove(client);
+        }
+        expired
+    }
+}
+
+impl Server {
+    /// Start the lease of a subscribed client.
+    pub(crate) fn grant_lease(&mut self, client_id: NodeId) {
+        self.client_leases.grant(client_id, Instant::now());
+        if let Some(deadline) = self.client_leases.next_expiry() {
+            self.scheduler
+                .schedule_no_later(Task::LeaseExpiry, deadline);
+        }
+    }
+
+    /// Renew the lease of the sender of a packet, nodes that are not subscribed are ignored.
+    /// The expiry task checks the renewals when it runs, so it does not need to be moved.
+    pub(crate) fn refresh_lease(&mut self, node_id: NodeId) -> bool {
+        self.client_leases.refresh(node_id, Instant::now())
+    }
+
+    /// Unsubscribe the clients that have been silent for longer than the lease duration.
+    pub(crate) fn expire_client_leases(&mut self) {
+        for client_id in self.client_leases.expire(Instant::now()) {
+            self.unsubscribe_client(client_id, UnsubscribeReason::LeaseExpired);
+            self.report_event(ServerEvent::ClientExpired(client_id));
+        }
+
+        if let Some(deadline) = self.client_leases.next_expiry() {
+            self.scheduler.schedule(Task::LeaseExpiry, deadline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_silent_clients_only() {
+        let mut leases = ClientLeases::new(Duration::from_secs(10));
+        let start = Instant::now();
+        leases.grant(1, start);
+        leases.grant(2, start);
+
+        assert!(leases.refresh(2, start + Duration::from_secs(5)));
+        assert!(!leases.refresh(3, start));
+        assert_eq!(leases.next_expiry(), Some(start + Duration::from_secs(10)));
+
+        assert_eq!(leases.expire(start + Duration::from_secs(10)), vec![1]);
+        assert_eq!(leases.last_renewal(1), None);
+        assert_eq!(leases.next_expiry(), Some(start + Duration::from_secs(15)));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use wg_internal::network::{NodeId, SourceRoutingHeader};
+
+/// Feedback about the nodes of the network, the same the `RoutingHandler` weighs its paths with:
+/// the packets received through each node and the `Nack(Dropped)` each node sent.
+/// It lets the paths found by the `RoutingHandler` be compared with each other.
+#[derive(Debug, Default)]
+pub(crate) struct LinkCosts {
+    congestion: HashMap<NodeId, u32>, // node_id -> packets received through the node
+    nacks: HashMap<NodeId, u32>,      // node_id -> packets dropped by the node
+}
+
+/// Cost of a path summed over its intermediate hops, dropped packets weigh more than congestion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PathCost {
+    pub nacks: u32,
+    pub congestion: u32,
+}
+
+impl LinkCosts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a packet received through the hops of `srh`
+    pub fn on_packet(&mut self, srh: &SourceRoutingHeader) {
+        for hop in &srh.hops {
+            let count = self.congestion.entry(*hop).or_default();
+            *count = count.saturating_add(1);
+        }
+    }
+
+    /// Count a packet dropped by `node_id`
+    pub fn on_nack(&mut self, node_id: NodeId) {
+        let count = self.nacks.entry(node_id).or_default();
+        *count = count.saturating_add(1);
+    }
+
+    /// Cost of `path`, its endpoints excluded as they are the peer and the client themselves
+    pub fn path_cost(&self, path: &[NodeId]) -> PathCost {
+        let hops = path
+            .get(1..path.len().saturating_sub(1))
+            .unwrap_or_default();
+        hops.iter().fold(PathCost::default(), |cost, hop| PathCost {
+            nacks: cost
+                .nacks
+                .saturating_add(self.nacks.get(hop).copied().unwrap_or(0)),
+            congestion: cost
+                .congestion
+                .saturating_add(self.congestion.get(hop).copied().unwrap_or(0)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_the_intermediate_hops() {
+        let mut costs = LinkCosts::new();
+        costs.on_packet(&SourceRoutingHeader {
+            hop_index: 1,
+            hops: vec![1, 2, 3, 4],
+        });
+        costs.on_nack(3);
+        costs.on_nack(4);
+
+        assert_eq!(
+            costs.path_cost(&[1, 2, 3, 4]),
+            PathCost {
+                nacks: 1,
+                congestion: 2
+            }
+        );
+        assert_eq!(costs.path_cost(&[1, 4]), PathCost::default());
+        assert_eq!(costs.path_cost(&[]), PathCost::default());
+    }
+
+    #[test]
+    fn drops_weigh_more_than_congestion() {
+        let dropping = PathCost {
+            nacks: 1,
+            congestion: 0,
+        };
+        let congested = PathCost {
+            nacks: 0,
+            congestion: 100,
+        };
+        assert!(congested < dropping);
+    }
+}
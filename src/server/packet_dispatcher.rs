@@ -3,10 +3,10 @@ mod flooding_handlers;
 mod fragment_handlers;
 mod nack_handler;
 
-use super::Server;
+use super::{Server, ServerEvent};
 
-use crate::utils::check_packet_dest;
-use wg_internal::packet::{Packet, PacketType};
+use crate::utils::{check_packet_dest, check_routing_header};
+use wg_internal::packet::{NackType, Packet, PacketType};
 
 impl Server {
     /// Call the correct function for the received `Packet`
@@ -19,16 +19,45 @@ impl Server {
             return;
         }
 
+        // Every handler below relies on a well-formed routing header
+        if let Err(reason) = check_routing_header(&packet.routing_header) {
+            self.report_event(ServerEvent::ProtocolViolation {
+                source: packet.routing_header.hops.first().copied(),
+                session_id: packet.session_id,
+                reason: format!("malformed routing header: {reason}"),
+            });
+            // The source still gets to know that its fragment has not been received
+            if let PacketType::MsgFragment(frag) = &packet.pack_type {
+                self.send_nack(
+                    packet,
+                    frag.fragment_index,
+                    NackType::UnexpectedRecipient(self.id),
+                );
+            }
+            return;
+        }
+
         // Update heurisic congestions
         self.routing_handler
             .nodes_congestion(packet.routing_header.clone());
+        self.link_costs.on_packet(&packet.routing_header);
 
         // Check if the packet is for this server
         if !check_packet_dest(&packet.routing_header, self.id, &self.logger) {
             self.logger.log_warn("Packet has wrong destination!");
+            if let PacketType::MsgFragment(frag) = &packet.pack_type {
+                self.send_nack(
+                    packet,
+                    frag.fragment_index,
+                    NackType::UnexpectedRecipient(self.id),
+                );
+            }
             return;
         }
 
+        // Any packet from a subscribed client renews its lease
+        self.refresh_lease(packet.routing_header.hops[0]);
+
         match &packet.pack_type {
             PacketType::MsgFragment(frag) => {
                 self.fragment_handler(packet, frag);
@@ -29,8 +29,8 @@ impl Server {
         let next_hop = source_routing_header.hops[1];
         let ack = Packet::new_ack(source_routing_header, packet.session_id, fragment_index);
 
-        if let Err(msg) = self.send_packets_vec(&[ack], next_hop) {
-            self.logger.log_error(&msg);
+        if let Err(err) = self.send_packets_vec(&[ack], next_hop) {
+            self.logger.log_error(&err.to_string());
         }
     }
 
@@ -38,7 +38,7 @@ impl Server {
     pub(crate) fn ack_handler(&mut self, fragment_index: u64, session_id: SessionIdT) {
         let Some(entry) = self
             .sent_fragments_history
-            .remove(&(fragment_index, session_id))
+            .remove(fragment_index, session_id)
         else {
             self.logger.log_error(&format!(
                 "Failed to remove [ ({fragment_index}, {session_id}) ] key from sent fragments history"
@@ -46,6 +46,11 @@ impl Server {
             return;
         };
         self.logger
-            .log_debug(&format!("Packet history updated, removed {entry}"));
+            .log_debug(&format!("Packet history updated, removed {}", entry.packet));
+
+        // Release the next fragments towards the same client
+        if let Some(destination) = Self::destination(&entry.packet) {
+            self.window_on_ack(destination, session_id);
+        }
     }
 }
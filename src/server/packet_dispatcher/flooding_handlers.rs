@@ -8,6 +8,7 @@ use super::Server;
 use wg_internal::network::{NodeId, SourceRoutingHeader};
 use wg_internal::packet::{FloodRequest, NodeType, Packet};
 
+use crate::error::ServerError;
 use crate::packet_send::send_packet;
 use crate::utils::get_packet_type;
 
@@ -44,7 +45,7 @@ impl Server {
                 session_id,
                 flood_req.clone(),
             );
-            if let Err(err) = send_packet(sender, &packet) {
+            if let Err(err) = send_packet(sender, &packet, *id) {
                 self.logger
                     .log_error(&format!("[FLOODING] Sending to [DRONE-{id}]: {err}"));
             }
@@ -72,7 +73,7 @@ impl Server {
         (dest.unwrap(), packet)
     }
 
-    fn send_flood_response(&self, next_hop: NodeId, packet: &Packet) -> Result<(), String> {
+    fn send_flood_response(&self, next_hop: NodeId, packet: &Packet) -> Result<(), ServerError> {
         self.send_packets_vec(&[packet.clone()], next_hop)
     }
 
@@ -82,8 +83,8 @@ impl Server {
 
         let res = self.send_flood_response(dest, &packet);
 
-        if let Err(msg) = res {
-            self.logger.log_error(&msg);
+        if let Err(err) = res {
+            self.logger.log_error(&err.to_string());
         }
     }
 }
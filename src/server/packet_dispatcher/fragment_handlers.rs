@@ -1,36 +1,93 @@
 mod chunk_req_handlers;
+mod error_responses;
+mod search_handlers;
 mod tracker_handlers;
 
-use super::Server;
+use super::{Server, ServerEvent};
+use crate::error::{DatabaseError, ServerError};
+use crate::server::leases::UnsubscribeReason;
+use crate::server::reassembly::FragmentOutcome;
+use crate::server::scheduler::Task;
 
-use packet_forge::MessageType;
+use packet_forge::{MessageType, SessionIdT};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
 use wg_internal::{
-    network::SourceRoutingHeader,
+    network::{NodeId, SourceRoutingHeader},
     packet::{Fragment, Packet},
 };
 
 impl Server {
-    /// Call the correct function for the received `MessageType`
-    fn message_handler(&mut self, message: &MessageType, addressee_srh: &SourceRoutingHeader) {
+    /// Client the message claims to come from, `None` for the messages that do not name one
+    fn claimed_client(message: &MessageType) -> Option<NodeId> {
+        match message {
+            MessageType::SubscribeClient(msg) => Some(msg.client_id),
+            MessageType::UpdateFileList(msg) => Some(msg.client_id),
+            MessageType::RequestFileList(msg) => Some(msg.client_id),
+            MessageType::RequestFileListPage(msg) => Some(msg.client_id),
+            MessageType::RequestFileListChanges(msg) => Some(msg.client_id),
+            MessageType::RequestPeerList(msg) => Some(msg.client_id),
+            MessageType::UnsubscribeClient(msg) => Some(msg.client_id),
+            MessageType::Heartbeat(msg) => Some(msg.client_id),
+            MessageType::ChunkRequest(msg) => Some(msg.client_id),
+            MessageType::RequestFileSearch(msg) => Some(msg.client_id),
+            _ => None,
+        }
+    }
+
+    /// Call the correct function for the received `MessageType`.
+    /// `session_id` identifies the request in the error responses.
+    fn message_handler(
+        &mut self,
+        message: &MessageType,
+        addressee_srh: &SourceRoutingHeader,
+        session_id: SessionIdT,
+    ) {
         self.logger.log_info(&format!("Processing {message:?}"));
+
+        // Every request but SubscribeClient needs a subscribed client
+        if let Some(client_id) = Self::claimed_client(message) {
+            let subscribing = matches!(message, MessageType::SubscribeClient(_));
+            if !subscribing && !self.database.contains_client(client_id) {
+                self.logger.log_warn(&format!(
+                    "Received a request from [CLIENT-{client_id}] but the client is not subscribed"
+                ));
+                let err = ServerError::Database(DatabaseError::ClientNotFound(client_id));
+                self.send_error_response(client_id, session_id, &err, addressee_srh);
+                return;
+            }
+        }
+
         match message {
             MessageType::SubscribeClient(msg) => {
                 self.subscribe_client(msg, addressee_srh);
             }
             MessageType::UpdateFileList(msg) => {
-                self.update_file_list(msg);
+                self.update_file_list(msg, addressee_srh);
             }
             MessageType::RequestFileList(msg) => {
                 self.send_file_list(msg.client_id, addressee_srh);
             }
+            MessageType::RequestFileListPage(msg) => {
+                self.send_file_list_page(msg.client_id, msg.cursor, msg.page_size, addressee_srh);
+            }
+            MessageType::RequestFileListChanges(msg) => {
+                self.send_file_list_changes(msg, addressee_srh);
+            }
             MessageType::RequestPeerList(msg) => {
-                self.send_peer_list(msg, addressee_srh);
+                self.send_peer_list(msg, addressee_srh, session_id);
             }
             MessageType::UnsubscribeClient(msg) => {
-                self.unsubscribe_client(msg);
+                self.unsubscribe_client(msg.client_id, UnsubscribeReason::Requested);
+            }
+            MessageType::Heartbeat(msg) => {
+                self.handle_heartbeat(msg);
             }
             MessageType::ChunkRequest(msg) => {
-                self.handle_chunk_request(msg, addressee_srh);
+                self.handle_chunk_request(msg, addressee_srh, session_id);
+            }
+            MessageType::RequestFileSearch(msg) => {
+                self.send_search_results(msg, addressee_srh);
             }
             _ => {
                 self.logger
@@ -39,36 +96,107 @@ impl Server {
         }
     }
 
-    /// Add the `Fragment` to the map and process it when all the fragments have been received
+    /// Add the `Fragment` to the reassembly buffer and process the message when all the fragments have been received
     pub(crate) fn fragment_handler(&mut self, packet: &Packet, frag: &Fragment) {
         let client_id = packet.routing_header.hops[0];
         let key = (client_id, packet.session_id);
 
-        // Save fragment
-        let total_fragments = frag.total_n_fragments;
-        self.recv_fragments_map
-            .entry(key)
-            .or_default()
-            .push(frag.clone());
+        // Save fragment, duplicates are ignored
+        let outcome = self.reassembly_buffer.insert(key, frag);
+        if let FragmentOutcome::Invalid(msg) = &outcome {
+            self.logger.log_error(&format!(
+                "Discarded fragment [ ({}, {}) ] from [CLIENT-{client_id}]: {msg}",
+                frag.fragment_index, packet.session_id
+            ));
+            return;
+        }
 
-        // Send Ack back to the Client
+        // Send Ack back to the Client, also for duplicates since the previous Ack may have been lost
         self.send_ack(packet, frag.fragment_index);
 
-        // If all fragments are received, assemble the message
-        let mut fragments = self.recv_fragments_map.get(&key).unwrap().clone();
-        if fragments.len() as u64 == total_fragments {
-            let assembled = match self.packet_forge.assemble_dynamic(&mut fragments) {
-                Ok(message) => message,
-                Err(e) => {
-                    self.logger
-                        .log_error(&format!("An error occurred when assembling fragments: {e}"));
-                    return;
+        // Partial messages and delivered sessions are both freed by the expiry task
+        if matches!(
+            outcome,
+            FragmentOutcome::Incomplete | FragmentOutcome::Complete(_)
+        ) {
+            if let Some(deadline) = self.reassembly_buffer.next_expiry() {
+                self.scheduler
+                    .schedule_no_later(Task::ReassemblyExpiry, deadline);
+            }
+        }
+
+        match outcome {
+            FragmentOutcome::Incomplete => {}
+            FragmentOutcome::Duplicate => {
+                self.logger.log_debug(&format!(
+                    "Ignoring duplicate fragment [ ({}, {}) ] from [CLIENT-{client_id}]",
+                    frag.fragment_index, packet.session_id
+                ));
+            }
+            FragmentOutcome::Complete(mut fragments) => {
+                // All fragments are received, assemble the message
+                let assembled = match self.packet_forge.assemble_dynamic(&mut fragments) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        self.logger.log_error(&format!(
+                            "An error occurred when assembling fragments: {e}"
+                        ));
+                        return;
+                    }
+                };
+
+                // A node may only speak for itself
+                if let Some(claimed) = Self::claimed_client(&assembled) {
+                    if claimed != client_id {
+                        self.report_event(ServerEvent::ProtocolViolation {
+                            source: Some(client_id),
+                            session_id: packet.session_id,
+                            reason: format!(
+                                "[NODE-{client_id}] sent a message on behalf of [CLIENT-{claimed}]"
+                            ),
+                        });
+                        return;
+                    }
                 }
-            };
 
-            let mut addressee_srh = packet.routing_header.get_reversed();
-            addressee_srh.increase_hop_index();
-            self.message_handler(&assembled, &addressee_srh);
+                let mut addressee_srh = packet.routing_header.get_reversed();
+                addressee_srh.increase_hop_index();
+
+                // A bug triggered by one message must not take the whole server down.
+                // The database is crash-safe, the in-memory state may miss the updates of the failed handler.
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                    self.message_handler(&assembled, &addressee_srh, packet.session_id);
+                }));
+                if let Err(payload) = handled {
+                    let reason = payload
+                        .downcast_ref::<&str>()
+                        .map(ToString::to_string)
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    self.logger.log_error(&format!(
+                        "Handler of message from [CLIENT-{client_id}] panicked: {reason}"
+                    ));
+                    self.report_event(ServerEvent::HandlerPanicked {
+                        source: client_id,
+                        session_id: packet.session_id,
+                        reason,
+                    });
+                }
+            }
+            FragmentOutcome::Invalid(_) => {}
+        }
+    }
+
+    /// Discard the incoming messages that have not been completed in time.
+    pub(crate) fn expire_reassembly_buffer(&mut self) {
+        for ((client_id, session_id), received) in self.reassembly_buffer.expire(Instant::now()) {
+            self.logger.log_warn(&format!(
+                "Discarded incomplete message [ session {session_id} ] from [CLIENT-{client_id}] with {received} fragments received"
+            ));
+        }
+
+        if let Some(deadline) = self.reassembly_buffer.next_expiry() {
+            self.scheduler.schedule(Task::ReassemblyExpiry, deadline);
         }
     }
 }
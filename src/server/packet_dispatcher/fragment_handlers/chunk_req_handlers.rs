@@ -1,91 +1,123 @@
-use crate::server::video_chunker::get_video_chunks;
-
 use super::Server;
+use crate::error::{DatabaseError, ServerError};
 
 use bytes::Bytes;
-use packet_forge::{ChunkRequest, ChunkResponse, ClientType, Index};
+use packet_forge::{ChunkRequest, ChunkResponse, ClientType, FileHash, Index, SessionIdT};
 use wg_internal::network::SourceRoutingHeader;
 
+/// Indexes of the chunks asked by `index`, checked against the `total_n_chunks` of the file
+/// so that nothing is sent for a request that cannot be served in full.
+/// ### Error
+/// Returns `DatabaseError::ChunkNotFound` for the first index past the end of the file.
+fn requested_chunks(
+    file: FileHash,
+    index: &Index,
+    total_n_chunks: u32,
+) -> Result<Vec<u32>, DatabaseError> {
+    match index {
+        Index::All => Ok((0..total_n_chunks).collect()),
+        Index::Indexes(indexes) => match indexes.iter().find(|i| **i >= total_n_chunks) {
+            Some(index) => Err(DatabaseError::ChunkNotFound {
+                file,
+                index: *index,
+            }),
+            None => Ok(indexes.clone()),
+        },
+    }
+}
+
 impl Server {
+    /// Send the requested chunks, the client gets an error response if they cannot be read.
     pub(crate) fn handle_chunk_request(
         &mut self,
         message: &ChunkRequest,
         addressee_srh: &SourceRoutingHeader,
+        session_id: SessionIdT,
     ) {
         let client_type = self.database.get_client_type(message.client_id);
 
         let res = match client_type {
             Ok(ClientType::Song) => self.handle_song_req(message, addressee_srh),
             Ok(ClientType::Video) => self.handle_video_req(message, addressee_srh),
-            Err(msg) => Err(msg),
+            Err(err) => Err(err.into()),
         };
 
-        if let Err(msg) = res {
-            self.logger.log_error(&msg);
+        if let Err(err) = res {
+            self.logger.log_error(&err.to_string());
+            self.send_error_response(message.client_id, session_id, &err, addressee_srh);
         }
     }
 
-    /// Get the requested song data from the database and sends its chunk to the client
+    /// Get the requested song data from the database and sends its chunk to the client.
+    /// `Index::All` sends every stored segment in order, starting from the `ts0` playlist.
+    /// A request with an index past the last segment is refused before any segment is sent.
     fn handle_song_req(
         &mut self,
         message: &ChunkRequest,
         addressee_srh: &SourceRoutingHeader,
-    ) -> Result<(), String> {
-        // For each index in ChunkRequest send ChunkResponse
-        if let Index::Indexes(vec) = &message.chunk_index {
-            for chunk_index in vec {
-                // Get segment from db
-                let prefix = &format!("ts{chunk_index}");
-                let segment = self.database.get_song_payload(prefix, message.file_hash)?;
-
-                // Build ChunkResponse
-                let chunk_data = Bytes::from(segment);
-                let chunk_res = ChunkResponse::new(message.file_hash, *chunk_index, 0, chunk_data);
-
-                // Retrieve new best path from server to client, otherwise use incoming one
-                let srh = if let Some(new_srh) = self.get_path(self.id, message.client_id) {
-                    new_srh
-                } else {
-                    self.logger
-                                             .log_error("[CHUNK RESPONSE - SONG] An error occurred: failed to get routing path, using reversed sender path");
-                    addressee_srh.clone()
-                };
-                let next_hop = srh.hops[srh.hop_index];
-
-                // Disassemble ChunkResponse into Packets
-                let packets = match self.packet_forge.disassemble(chunk_res.clone(), &srh) {
-                    Ok(packets) => packets,
-                    Err(msg) => {
-                        return Err(format!(
-                            "{chunk_res:?}\n Error while disassembling song: {msg}"
-                        ));
-                    }
-                };
-
-                self.send_save_packets(&packets, next_hop)?;
-
-                self.logger.log_info(&format!(
-                    "[CHUNK RESPONSE - SONG] Forwarded chunk for song: {} to client-{}",
-                    message.file_hash, message.client_id
-                ));
-            }
-            return Ok(());
+    ) -> Result<(), ServerError> {
+        let total_n_chunks = self.database.get_song_segments_count(message.file_hash)?;
+        if total_n_chunks == 0 {
+            return Err(DatabaseError::PayloadNotFound(message.file_hash).into());
         }
 
-        Err("ChunkRequest for songs does not handle requests for all chunks!".to_string())
+        let chunk_indexes =
+            requested_chunks(message.file_hash, &message.chunk_index, total_n_chunks)?;
+
+        // Retrieve new best path from server to client, otherwise use incoming one
+        let srh = if let Some(new_srh) = self.get_path(self.id, message.client_id) {
+            new_srh
+        } else {
+            self.logger
+                .log_error("[CHUNK RESPONSE - SONG] An error occurred: failed to get routing path, using reversed sender path");
+            addressee_srh.clone()
+        };
+        let next_hop = srh.hops[srh.hop_index];
+
+        // For each index in ChunkRequest send ChunkResponse
+        for chunk_index in chunk_indexes {
+            // Get segment from db
+            let segment = self
+                .database
+                .get_song_segment(message.file_hash, chunk_index)?;
+
+            // Build ChunkResponse
+            let chunk_data = Bytes::from(segment);
+            let chunk_res =
+                ChunkResponse::new(message.file_hash, chunk_index, total_n_chunks, chunk_data);
+
+            // Disassemble ChunkResponse into Packets
+            let packets = match self.packet_forge.disassemble(chunk_res.clone(), &srh) {
+                Ok(packets) => packets,
+                Err(msg) => {
+                    return Err(ServerError::Disassemble(format!("{chunk_res:?}\n {msg}")));
+                }
+            };
+
+            self.send_save_packets(&packets, next_hop)?;
+
+            self.logger.log_info(&format!(
+                "[CHUNK RESPONSE - SONG] Forwarded chunk {chunk_index}/{total_n_chunks} for song: {} to client-{}",
+                message.file_hash, message.client_id
+            ));
+        }
+        Ok(())
     }
 
-    /// Get the requested video data from the database and sends its chunk to the client
+    /// Get the requested video data from the database and sends the requested chunks to the client.
+    /// `Index::All` sends the whole video.
+    /// A request with an index past the last chunk is refused before any chunk is sent.
     fn handle_video_req(
         &mut self,
         message: &ChunkRequest,
         addressee_srh: &SourceRoutingHeader,
-    ) -> Result<(), String> {
-        // Retrieve data of the video from the database
-        let video_data = self.database.get_video_payload(message.file_hash)?;
+    ) -> Result<(), ServerError> {
+        // Retrieve the chunks layout of the video from the database
+        let chunks_info = self.database.get_video_chunks_info(message.file_hash)?;
+        let total_n_chunks = chunks_info.n_chunks;
 
-        // Split the video into chunks
-        let video_chunks = get_video_chunks(video_data);
+        let chunk_indexes =
+            requested_chunks(message.file_hash, &message.chunk_index, total_n_chunks)?;
 
         // Retrieve new best path from server to client, otherwise use incoming one
         let srh = if let Some(new_srh) = self.get_path(self.id, message.client_id) {
@@ -96,36 +128,56 @@ impl Server {
         };
         let next_hop = srh.hops[srh.hop_index];
 
-        let total_n_chunks = video_chunks.len() as u32;
-
-        for (index, chunk) in video_chunks.enumerate() {
-            let Ok(chunk_index) = u32::try_from(index) else {
-                return Err("Could not convert chunk_index to u32".to_string());
-            };
+        for chunk_index in chunk_indexes {
+            // Read only the requested chunk
+            let chunk =
+                self.database
+                    .get_video_chunk(message.file_hash, chunk_index, &chunks_info)?;
             let chunk_res = ChunkResponse::new(
                 message.file_hash,
                 chunk_index,
                 total_n_chunks,
-                chunk.clone(),
+                Bytes::from(chunk),
             );
 
             // Disassemble ChunkResponse into Packets
             let packets = match self.packet_forge.disassemble(chunk_res.clone(), &srh) {
                 Ok(packets) => packets,
                 Err(msg) => {
-                    return Err(format!(
-                        "{chunk_res:?}\n Error while disassembling video: {msg}"
-                    ));
+                    return Err(ServerError::Disassemble(format!("{chunk_res:?}\n {msg}")));
                 }
             };
 
             self.send_save_packets(&packets, next_hop)?;
 
             self.logger.log_info(&format!(
-                "[CHUNK RESPONSE - VIDEO] Forwarded chunk for video {} to client-{}",
+                "[CHUNK RESPONSE - VIDEO] Forwarded chunk {chunk_index}/{total_n_chunks} for video {} to client-{}",
                 message.file_hash, message.client_id
             ));
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_lists_every_chunk() {
+        assert_eq!(requested_chunks(1, &Index::All, 3).unwrap(), vec![0, 1, 2]);
+        assert!(requested_chunks(1, &Index::All, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_indexes_past_the_end() {
+        let index = Index::Indexes(vec![2, 0]);
+        assert_eq!(requested_chunks(1, &index, 3).unwrap(), vec![2, 0]);
+
+        let index = Index::Indexes(vec![0, 5, 1]);
+        assert!(matches!(
+            requested_chunks(1, &index, 3),
+            Err(DatabaseError::ChunkNotFound { file: 1, index: 5 })
+        ));
+    }
+}
@@ -0,0 +1,60 @@
+use super::Server;
+use crate::error::{DatabaseError, ServerError};
+
+use packet_forge::{ErrorCode, ResponseError, SessionIdT};
+use wg_internal::network::{NodeId, SourceRoutingHeader};
+
+/// Code telling the client how to react to `err`
+fn error_code(err: &ServerError) -> ErrorCode {
+    match err {
+        ServerError::Database(DatabaseError::ClientNotFound(_)) => ErrorCode::NotSubscribed,
+        ServerError::Database(
+            DatabaseError::SongNotFound(_)
+            | DatabaseError::VideoNotFound(_)
+            | DatabaseError::PayloadNotFound(_),
+        ) => ErrorCode::FileNotFound,
+        ServerError::Database(DatabaseError::ChunkNotFound { .. }) => ErrorCode::ChunkNotFound,
+        ServerError::HashMismatch { .. } => ErrorCode::InvalidRequest,
+        _ => ErrorCode::Internal,
+    }
+}
+
+impl Server {
+    /// Tell the client that its request, identified by the session it was sent with, failed.
+    /// The client can then fail fast or ask another server instead of waiting.
+    pub(crate) fn send_error_response(
+        &mut self,
+        client_id: NodeId,
+        request_session: SessionIdT,
+        err: &ServerError,
+        addressee_srh: &SourceRoutingHeader,
+    ) {
+        let response =
+            ResponseError::new(self.id, error_code(err), request_session, err.to_string());
+
+        if let Err(err) =
+            self.send_message(client_id, response, Some(addressee_srh), "RESPONSE ERROR")
+        {
+            self.logger.log_error(&format!("[RESPONSE ERROR] - {err}"));
+            return;
+        }
+
+        self.logger.log_info(&format!(
+            "[RESPONSE ERROR] Sent \"{err}\" for request [ session {request_session} ] of [CLIENT-{client_id}]"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_chunks_are_not_missing_files() {
+        let err = ServerError::from(DatabaseError::ChunkNotFound { file: 1, index: 5 });
+        assert!(matches!(error_code(&err), ErrorCode::ChunkNotFound));
+
+        let err = ServerError::from(DatabaseError::PayloadNotFound(1));
+        assert!(matches!(error_code(&err), ErrorCode::FileNotFound));
+    }
+}
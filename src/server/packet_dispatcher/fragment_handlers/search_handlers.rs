@@ -0,0 +1,71 @@
+use super::Server;
+use crate::database::MAX_PAGE_SIZE;
+
+use packet_forge::{ClientType, FileMetadata, RequestFileSearch, ResponseFileSearch};
+use wg_internal::network::SourceRoutingHeader;
+
+impl Server {
+    /// Send to the requesting client a page of the files matching its query.
+    /// A `page_size` of 0 or above `MAX_PAGE_SIZE` returns `MAX_PAGE_SIZE` files.
+    pub(crate) fn send_search_results(
+        &mut self,
+        message: &RequestFileSearch,
+        addressee_srh: &SourceRoutingHeader,
+    ) {
+        let limit = match message.page_size as usize {
+            0 => MAX_PAGE_SIZE,
+            page_size => page_size.min(MAX_PAGE_SIZE),
+        };
+
+        let client_type = self.database.get_client_type(message.client_id);
+
+        let page = match client_type {
+            Ok(ClientType::Song) => self
+                .database
+                .search_songs(&message.query, message.client_id, message.cursor, limit)
+                .map(|page| {
+                    let files = page.files.into_iter().map(FileMetadata::Song).collect();
+                    (files, page.next_cursor, page.skipped)
+                }),
+            Ok(ClientType::Video) => self
+                .database
+                .search_videos(&message.query, message.client_id, message.cursor, limit)
+                .map(|page| {
+                    let files = page.files.into_iter().map(FileMetadata::Video).collect();
+                    (files, page.next_cursor, page.skipped)
+                }),
+            Err(err) => Err(err),
+        };
+
+        let (files, next_cursor, skipped): (Vec<FileMetadata>, _, _) = match page {
+            Ok(page) => page,
+            Err(err) => {
+                self.logger
+                    .log_error(&format!("[RESPONSE FILE SEARCH] {err}"));
+                return;
+            }
+        };
+        if !skipped.is_empty() {
+            self.logger.log_error(&format!(
+                "[RESPONSE FILE SEARCH] Skipped undecodable entries {skipped:?}"
+            ));
+        }
+
+        self.logger.log_debug(&format!(
+            "[RESPONSE FILE SEARCH] {} files found for {:?}, next cursor: {next_cursor:?}",
+            files.len(),
+            message.query
+        ));
+        let response = ResponseFileSearch::new(self.id, files, next_cursor);
+
+        if let Err(err) = self.send_message(
+            message.client_id,
+            response,
+            Some(addressee_srh),
+            "RESPONSE FILE SEARCH",
+        ) {
+            self.logger
+                .log_error(&format!("[RESPONSE FILE SEARCH] - {err}"));
+        }
+    }
+}
@@ -1,39 +1,129 @@
 use super::Server;
+use crate::database::{FileKind, MAX_PAGE_SIZE};
+use crate::error::{DatabaseError, ServerError};
+use crate::server::leases::UnsubscribeReason;
+use crate::server::link_costs::PathCost;
+use crate::server::scheduler::Task;
+use crate::server::ValidationPolicy;
 
 use packet_forge::*;
+use std::collections::HashSet;
+use std::time::{Instant, SystemTime};
 use wg_internal::network::{NodeId, SourceRoutingHeader};
 
 impl Server {
-    fn add_new_song(&self, song_metadata: &SongMetaData, client_id: NodeId) {
-        if let Err(msg) = self.database.insert_song_peer(song_metadata, client_id) {
-            self.logger.log_error(&msg);
-        }
-        self.logger
-            .log_debug(&format!("Added new Song [ {song_metadata:?} ]"));
+    /// The client shares the song, returns the key it is stored under
+    fn add_new_song(
+        &self,
+        song_metadata: &SongMetaData,
+        client_id: NodeId,
+    ) -> Result<FileHash, DatabaseError> {
+        let song_id = match self.database.insert_song_peer(song_metadata, client_id) {
+            Ok(song_id) => song_id,
+            Err(err) => {
+                self.logger.log_error(&err.to_string());
+                return Err(err);
+            }
+        };
+        self.logger.log_debug(&format!(
+            "Added new Song [ {song_metadata:?} ] with ID [ {song_id} ]"
+        ));
+        Ok(song_id)
     }
 
-    fn remove_existing_song(&self, song_id: FileHash) {
-        if let Err(msg) = self.database.remove_song(song_id) {
-            self.logger.log_error(&msg);
-        }
-        self.logger
-            .log_debug(&format!("Removed Song with ID [ {song_id} ]"));
+    /// The client stopped sharing the song, other peers keep it available.
+    /// The song is looked up by its metadata, it may be stored under another id after a collision.
+    /// ### Error
+    /// Returns `DatabaseError::PeerNotFound` if the client does not share the song.
+    fn remove_existing_song(
+        &self,
+        song_metadata: &SongMetaData,
+        client_id: NodeId,
+    ) -> Result<FileHash, DatabaseError> {
+        let res = self.database.find_key(song_metadata).and_then(|song_id| {
+            let song_id = song_id.ok_or(DatabaseError::SongNotFound(song_metadata.id))?;
+            if !self
+                .database
+                .get_song_entry(song_id)?
+                .peers
+                .contains(&client_id)
+            {
+                return Err(DatabaseError::PeerNotFound {
+                    file: song_id,
+                    peer: client_id,
+                });
+            }
+            self.database.remove_song_peer(song_id, client_id)?;
+            Ok(song_id)
+        });
+        let song_id = match res {
+            Ok(song_id) => song_id,
+            Err(err) => {
+                self.logger.log_error(&err.to_string());
+                return Err(err);
+            }
+        };
+        self.logger.log_debug(&format!(
+            "Removed [CLIENT-{client_id}] from the peers of Song with ID [ {song_id} ]"
+        ));
+        Ok(song_id)
     }
 
-    fn add_new_video(&self, video_metadata: &VideoMetaData, client_id: NodeId) {
-        if let Err(msg) = self.database.insert_video_peer(video_metadata, client_id) {
-            self.logger.log_error(&msg);
-        }
-        self.logger
-            .log_debug(&format!("Added new Video [ {video_metadata:?} ]"));
+    /// The client shares the video, returns the key it is stored under
+    fn add_new_video(
+        &self,
+        video_metadata: &VideoMetaData,
+        client_id: NodeId,
+    ) -> Result<FileHash, DatabaseError> {
+        let video_id = match self.database.insert_video_peer(video_metadata, client_id) {
+            Ok(video_id) => video_id,
+            Err(err) => {
+                self.logger.log_error(&err.to_string());
+                return Err(err);
+            }
+        };
+        self.logger.log_debug(&format!(
+            "Added new Video [ {video_metadata:?} ] with ID [ {video_id} ]"
+        ));
+        Ok(video_id)
     }
 
-    fn remove_existing_video(&self, video_id: FileHash) {
-        if let Err(msg) = self.database.remove_video(video_id) {
-            self.logger.log_error(&msg);
-        }
-        self.logger
-            .log_debug(&format!("Removed Video with ID [ {video_id} ]"));
+    /// The client stopped sharing the video, other peers keep it available.
+    /// The video is looked up by its metadata, it may be stored under another id after a collision.
+    /// ### Error
+    /// Returns `DatabaseError::PeerNotFound` if the client does not share the video.
+    fn remove_existing_video(
+        &self,
+        video_metadata: &VideoMetaData,
+        client_id: NodeId,
+    ) -> Result<FileHash, DatabaseError> {
+        let res = self.database.find_key(video_metadata).and_then(|video_id| {
+            let video_id = video_id.ok_or(DatabaseError::VideoNotFound(video_metadata.id))?;
+            if !self
+                .database
+                .get_video_entry(video_id)?
+                .peers
+                .contains(&client_id)
+            {
+                return Err(DatabaseError::PeerNotFound {
+                    file: video_id,
+                    peer: client_id,
+                });
+            }
+            self.database.remove_video_peer(video_id, client_id)?;
+            Ok(video_id)
+        });
+        let video_id = match res {
+            Ok(video_id) => video_id,
+            Err(err) => {
+                self.logger.log_error(&err.to_string());
+                return Err(err);
+            }
+        };
+        self.logger.log_debug(&format!(
+            "Removed [CLIENT-{client_id}] from the peers of Video with ID [ {video_id} ]"
+        ));
+        Ok(video_id)
     }
 
     /// Add client information to the database
@@ -56,78 +146,189 @@ impl Server {
         }
 
         // Add client to database `client_tree`
-        if let Err(msg) = self
+        if let Err(err) = self
             .database
             .insert_client(message.client_id, &message.client_type)
         {
-            self.logger.log_error(&msg);
+            self.logger.log_error(&err.to_string());
             return;
         }
+        self.grant_lease(message.client_id);
 
-        // Add files to song or video
-        for file in &message.available_files {
-            match file {
-                FileMetadata::Song(song_metadata) => {
-                    if let Err(msg) = Self::check_hash(song_metadata.id, song_metadata) {
-                        self.logger.log_error(&msg);
-                        continue;
-                    }
-                    self.add_new_song(song_metadata, message.client_id);
-                }
-                FileMetadata::Video(video_metadata) => {
-                    if let Err(msg) = Self::check_hash(video_metadata.id, video_metadata) {
-                        self.logger.log_error(&msg);
-                        continue;
-                    }
-                    self.add_new_video(video_metadata, message.client_id);
-                }
-            };
-        }
+        // Add files to song or video, each one gets an outcome
+        let outcomes: Vec<(FileHash, SubmissionOutcome)> = message
+            .available_files
+            .iter()
+            .map(|file| self.submit_file(message.client_id, file, &FileStatus::New))
+            .collect();
 
         self.logger.log_info(&format!(
-            "Client {} subscribed with success! Sending file list...",
+            "Client {} subscribed with success! Sending the first page of the file list...",
             message.client_id
         ));
 
-        self.send_file_list(message.client_id, addressee_srh);
+        self.send_file_list_page(message.client_id, None, 0, addressee_srh);
+        self.send_submission_report(message.client_id, outcomes, addressee_srh);
+        self.schedule_catalog_push();
     }
 
-    /// Given a client, remove or add information about a file that it shares.
-    pub(crate) fn update_file_list(&mut self, message: &UpdateFileList) {
-        if !self.database.contains_client(message.client_id) {
-            self.logger.log_warn(&format!(
-                "Received UpdateFileList for [CLIENT-{}] but no client was found. File list: {:?}",
-                message.client_id, message.updated_files
-            ));
-            return;
-        }
+    /// Given a subscribed client, remove or add information about a file that it shares.
+    /// Every file is validated and its outcome is reported to the client.
+    pub(crate) fn update_file_list(
+        &mut self,
+        message: &UpdateFileList,
+        addressee_srh: &SourceRoutingHeader,
+    ) {
+        let outcomes: Vec<(FileHash, SubmissionOutcome)> = message
+            .updated_files
+            .iter()
+            .map(|(file_metadata, file_status)| {
+                self.submit_file(message.client_id, file_metadata, file_status)
+            })
+            .collect();
 
-        for (file_metadata, file_status) in &message.updated_files {
-            match file_metadata {
-                FileMetadata::Song(song_metadata) => {
-                    if let Err(msg) = Self::check_hash(song_metadata.id, song_metadata) {
-                        self.logger.log_error(&msg);
-                    }
+        self.logger
+            .log_info(&format!("Client-{} file list updated!", message.client_id));
+        self.send_submission_report(message.client_id, outcomes, addressee_srh);
+        self.schedule_catalog_push();
+    }
 
-                    match file_status {
-                        FileStatus::New => self.add_new_song(song_metadata, message.client_id),
-                        FileStatus::Deleted => self.remove_existing_song(song_metadata.id),
-                    }
+    /// Validate a file submitted by the client according to the `ValidationPolicy`, then add or remove it.
+    /// Returns the outcome to report to the client, with the key assigned to the file if it differs from the submitted id.
+    fn submit_file(
+        &mut self,
+        client_id: NodeId,
+        file_metadata: &FileMetadata,
+        file_status: &FileStatus,
+    ) -> (FileHash, SubmissionOutcome) {
+        let (file_hash, check) = match file_metadata {
+            FileMetadata::Song(metadata) => (metadata.id, Self::check_hash(metadata.id, metadata)),
+            FileMetadata::Video(metadata) => (metadata.id, Self::check_hash(metadata.id, metadata)),
+        };
+
+        // Deleting a quarantined file only takes it out of the quarantine
+        if matches!(file_status, FileStatus::Deleted) {
+            match self.database.release_quarantined(client_id, file_metadata) {
+                Ok(true) => {
+                    self.logger.log_info(&format!(
+                        "[VALIDATION] - Released file [ {file_hash} ] of [CLIENT-{client_id}] from quarantine"
+                    ));
+                    return (file_hash, SubmissionOutcome::Accepted);
                 }
-                FileMetadata::Video(video_metadata) => {
-                    if let Err(msg) = Self::check_hash(video_metadata.id, video_metadata) {
-                        self.logger.log_error(&msg);
-                    }
+                Ok(false) => {}
+                Err(err) => {
+                    self.logger.log_error(&err.to_string());
+                    return (file_hash, SubmissionOutcome::Rejected(err.to_string()));
+                }
+            }
+        }
 
-                    match file_status {
-                        FileStatus::New => self.add_new_video(video_metadata, message.client_id),
-                        FileStatus::Deleted => self.remove_existing_video(video_metadata.id),
+        let mut warning = None;
+        if let Err(err) = check {
+            let reason = err.to_string();
+            match (self.validation_policy, file_status) {
+                (ValidationPolicy::AcceptWithWarning, _) => {
+                    self.logger.log_warn(&format!(
+                        "[VALIDATION] - Accepting file [ {file_hash} ] of [CLIENT-{client_id}]: {reason}"
+                    ));
+                    warning = Some(reason);
+                }
+                (ValidationPolicy::Quarantine, FileStatus::New) => {
+                    if let Err(err) =
+                        self.database
+                            .quarantine_file(client_id, file_metadata, SystemTime::now())
+                    {
+                        self.logger.log_error(&err.to_string());
+                        return (file_hash, SubmissionOutcome::Rejected(reason));
                     }
+                    self.scheduler.schedule_no_later(
+                        Task::QuarantineExpiry,
+                        Instant::now() + self.quarantine_expiry,
+                    );
+                    self.logger.log_warn(&format!(
+                        "[VALIDATION] - Quarantined file [ {file_hash} ] of [CLIENT-{client_id}]: {reason}"
+                    ));
+                    return (file_hash, SubmissionOutcome::Quarantined(reason));
+                }
+                // A deletion has nothing to quarantine
+                (ValidationPolicy::Reject | ValidationPolicy::Quarantine, _) => {
+                    self.logger.log_error(&format!(
+                        "[VALIDATION] - Rejected file [ {file_hash} ] of [CLIENT-{client_id}]: {reason}"
+                    ));
+                    return (file_hash, SubmissionOutcome::Rejected(reason));
                 }
             }
         }
-        self.logger
-            .log_info(&format!("Client-{} file list updated!", message.client_id));
+
+        // A copy quarantined with the wrong FileHash is replaced by the file that passed validation
+        if matches!(file_status, FileStatus::New) {
+            match self.database.release_quarantined(client_id, file_metadata) {
+                Ok(true) => self.logger.log_info(&format!(
+                    "[VALIDATION] - Released file [ {file_hash} ] of [CLIENT-{client_id}] from quarantine"
+                )),
+                Ok(false) => {}
+                Err(err) => self.logger.log_error(&err.to_string()),
+            }
+        }
+
+        let res = match (file_metadata, file_status) {
+            (FileMetadata::Song(metadata), FileStatus::New) => {
+                self.add_new_song(metadata, client_id)
+            }
+            (FileMetadata::Video(metadata), FileStatus::New) => {
+                self.add_new_video(metadata, client_id)
+            }
+            (FileMetadata::Song(metadata), FileStatus::Deleted) => {
+                self.remove_existing_song(metadata, client_id)
+            }
+            (FileMetadata::Video(metadata), FileStatus::Deleted) => {
+                self.remove_existing_video(metadata, client_id)
+            }
+        };
+
+        let outcome = match res {
+            // Another file already uses the submitted id, the client has to use the assigned one
+            Ok(assigned) if assigned != file_hash && matches!(file_status, FileStatus::New) => {
+                self.logger.log_warn(&format!(
+                    "[VALIDATION] - File [ {file_hash} ] of [CLIENT-{client_id}] collides with another file, stored as [ {assigned} ]"
+                ));
+                SubmissionOutcome::Rekeyed(assigned)
+            }
+            Ok(_) => warning.map_or(
+                SubmissionOutcome::Accepted,
+                SubmissionOutcome::AcceptedWithWarning,
+            ),
+            Err(DatabaseError::HashCollision(_)) => SubmissionOutcome::HashCollision,
+            Err(err) => SubmissionOutcome::Rejected(err.to_string()),
+        };
+        (file_hash, outcome)
+    }
+
+    /// Send to the client the outcome of each file it submitted.
+    fn send_submission_report(
+        &mut self,
+        client_id: NodeId,
+        outcomes: Vec<(FileHash, SubmissionOutcome)>,
+        addressee_srh: &SourceRoutingHeader,
+    ) {
+        if outcomes.is_empty() {
+            return;
+        }
+
+        self.logger.log_debug(&format!(
+            "[RESPONSE FILE SUBMISSION] Outcomes for [CLIENT-{client_id}]: {outcomes:?}"
+        ));
+        let response = ResponseFileSubmission::new(self.id, outcomes);
+
+        if let Err(err) = self.send_message(
+            client_id,
+            response,
+            Some(addressee_srh),
+            "RESPONSE FILE SUBMISSION",
+        ) {
+            self.logger
+                .log_error(&format!("[RESPONSE FILE SUBMISSION] - {err}"));
+        }
     }
 
     /// Send all the file available to the requesting client
@@ -171,8 +372,8 @@ impl Server {
                         .collect(),
                 )
             }
-            Err(msg) => {
-                self.logger.log_error(&msg);
+            Err(err) => {
+                self.logger.log_error(&err.to_string());
                 return;
             }
         };
@@ -201,8 +402,8 @@ impl Server {
         };
 
         let next_hop = srh.hops[srh.hop_index];
-        if let Err(msg) = self.send_save_packets(&packets, next_hop) {
-            self.logger.log_error(&msg);
+        if let Err(err) = self.send_save_packets(&packets, next_hop) {
+            self.logger.log_error(&err.to_string());
             return;
         }
 
@@ -210,61 +411,224 @@ impl Server {
             .log_info("[RESPONSE FILE LIST] Sent successfully!");
     }
 
-    /// Send a list of peers from which the requested file can be downloaded
-    pub(crate) fn send_peer_list(
+    /// Send a page of the files available to the requesting client, starting after `cursor`.
+    /// The response carries the catalog version read before the first file, to ask for the following changes.
+    /// A `page_size` of 0 or above `MAX_PAGE_SIZE` returns `MAX_PAGE_SIZE` files.
+    pub(crate) fn send_file_list_page(
         &mut self,
-        message: &RequestPeerList,
+        client_id: NodeId,
+        cursor: Option<FileHash>,
+        page_size: u32,
         addressee_srh: &SourceRoutingHeader,
     ) {
-        let client_type = self.database.get_client_type(message.client_id);
+        let limit = match page_size as usize {
+            0 => MAX_PAGE_SIZE,
+            page_size => page_size.min(MAX_PAGE_SIZE),
+        };
 
-        let file_peers = match client_type {
-            Ok(ClientType::Song) => {
-                // Retrieve the requested file
-                let song = self.database.get_song_entry(message.file_hash);
-                if let Err(msg) = song {
-                    self.logger.log_error(&msg);
-                    return;
-                }
+        // Read before the page, changes made while listing are returned by the next RequestFileListChanges
+        let catalog_version = match self.database.get_catalog_version() {
+            Ok(version) => version,
+            Err(err) => {
+                self.logger
+                    .log_error(&format!("[RESPONSE FILE LIST PAGE] {err}"));
+                return;
+            }
+        };
 
-                song.unwrap().peers
+        let page = match self.database.get_client_type(client_id) {
+            Ok(ClientType::Song) => {
+                self.database
+                    .get_songs_page(client_id, cursor, limit)
+                    .map(|page| {
+                        let files = page.files.into_iter().map(FileMetadata::Song).collect();
+                        (files, page.next_cursor, page.skipped)
+                    })
             }
             Ok(ClientType::Video) => {
-                // Retrieve the requested file
-                let song = self.database.get_video_entry(message.file_hash);
-                if let Err(msg) = song {
-                    self.logger.log_error(&msg);
-                    return;
-                }
+                self.database
+                    .get_videos_page(client_id, cursor, limit)
+                    .map(|page| {
+                        let files = page.files.into_iter().map(FileMetadata::Video).collect();
+                        (files, page.next_cursor, page.skipped)
+                    })
+            }
+            Err(err) => Err(err),
+        };
 
-                song.unwrap().peers
+        let (files, next_cursor, skipped): (Vec<FileMetadata>, _, _) = match page {
+            Ok(page) => page,
+            Err(err) => {
+                self.logger
+                    .log_error(&format!("[RESPONSE FILE LIST PAGE] {err}"));
+                return;
             }
-            Err(msg) => {
-                self.logger.log_error(&msg);
+        };
+        if !skipped.is_empty() {
+            self.logger.log_error(&format!(
+                "[RESPONSE FILE LIST PAGE] Skipped undecodable entries {skipped:?}"
+            ));
+        }
+
+        let response = ResponseFileListPage::new(self.id, files, next_cursor, catalog_version);
+
+        if let Err(err) = self.send_message(
+            client_id,
+            response,
+            Some(addressee_srh),
+            "RESPONSE FILE LIST PAGE",
+        ) {
+            self.logger
+                .log_error(&format!("[RESPONSE FILE LIST PAGE] - {err}"));
+            return;
+        }
+
+        // Following changes are pushed to the client
+        self.catalog_subscribers
+            .mark_seen(client_id, catalog_version);
+    }
+
+    /// Send the files added, updated or removed since the catalog version known by the client.
+    /// At most `MAX_PAGE_SIZE` changes are sent, the response version tells where to continue from.
+    pub(crate) fn send_file_list_changes(
+        &mut self,
+        message: &RequestFileListChanges,
+        addressee_srh: &SourceRoutingHeader,
+    ) {
+        let changes = self
+            .database
+            .get_client_type(message.client_id)
+            .and_then(|client_type| {
+                self.database.get_catalog_changes(
+                    FileKind::from(&client_type),
+                    message.since_version,
+                    message.client_id,
+                    MAX_PAGE_SIZE,
+                )
+            });
+
+        let changes = match changes {
+            Ok(changes) => changes,
+            Err(err) => {
+                self.logger
+                    .log_error(&format!("[RESPONSE FILE LIST CHANGES] {err}"));
                 return;
             }
         };
 
-        // Create the vector to send to the client
-        let peers_info: Vec<PeerInfo> = file_peers
-            .iter()
-            .filter_map(|peer| {
-                if let Some(srh) = self.get_path(*peer, message.client_id) {
-                    return Some(PeerInfo {
-                        client_id: *peer,
-                        path: srh.hops,
-                    });
-                }
+        self.logger.log_debug(&format!(
+            "[RESPONSE FILE LIST CHANGES] {} changes for [CLIENT-{}] from version {} to {}",
+            changes.changes.len(),
+            message.client_id,
+            message.since_version,
+            changes.version
+        ));
+        let version = changes.version;
+        let response = ResponseFileListChanges::new(self.id, changes.changes, version);
+
+        if let Err(err) = self.send_message(
+            message.client_id,
+            response,
+            Some(addressee_srh),
+            "RESPONSE FILE LIST CHANGES",
+        ) {
+            self.logger
+                .log_error(&format!("[RESPONSE FILE LIST CHANGES] - {err}"));
+            return;
+        }
 
-                if *peer == self.id {
-                    return Some(PeerInfo {
-                        client_id: *peer,
-                        path: addressee_srh.hops.clone(),
-                    });
-                }
-                None
+        self.catalog_subscribers
+            .mark_seen(message.client_id, version);
+    }
+
+    /// Order the peers sharing a file from the best to the worst source for `client_id`, keeping at most `peer_list_size`.
+    /// Peers are ranked by length of the best path, then by the drops and congestion met along it,
+    /// then by how recently their lease was renewed.
+    /// Peers with no route towards the client are marked unreachable and listed last.
+    fn rank_peers(&mut self, peers: &HashSet<NodeId>, client_id: NodeId) -> Vec<PeerInfo> {
+        let now = Instant::now();
+        let mut ranked: Vec<(Option<Vec<NodeId>>, PathCost, Option<Instant>, NodeId)> = Vec::new();
+        for peer in peers {
+            let path = self.get_path(*peer, client_id).map(|path| path.hops);
+            if path.is_none() {
+                self.logger.log_warn(&format!(
+                    "[RESPONSE PEER LIST] No route from [{peer}] to [CLIENT-{client_id}], marking it unreachable"
+                ));
+            }
+            let cost = path
+                .as_ref()
+                .map_or_else(PathCost::default, |path| self.link_costs.path_cost(path));
+            // The server is always alive
+            let renewed = if *peer == self.id {
+                Some(now)
+            } else {
+                self.client_leases.last_renewal(*peer)
+            };
+            ranked.push((path, cost, renewed, *peer));
+        }
+
+        ranked.sort_by(
+            |(path_a, cost_a, renewed_a, peer_a), (path_b, cost_b, renewed_b, peer_b)| {
+                path_a
+                    .is_none()
+                    .cmp(&path_b.is_none())
+                    .then_with(|| {
+                        path_a
+                            .as_ref()
+                            .map(Vec::len)
+                            .cmp(&path_b.as_ref().map(Vec::len))
+                    })
+                    .then_with(|| cost_a.cmp(cost_b))
+                    .then_with(|| renewed_b.cmp(renewed_a))
+                    .then_with(|| peer_a.cmp(peer_b))
+            },
+        );
+
+        ranked
+            .into_iter()
+            .take(self.peer_list_size)
+            .map(|(path, _, _, peer)| PeerInfo {
+                client_id: peer,
+                reachable: path.is_some(),
+                path: path.unwrap_or_default(),
             })
-            .collect();
+            .collect()
+    }
+
+    /// Send a ranked list of peers from which the requested file can be downloaded.
+    /// The peers that cannot currently reach the client are listed last, marked unreachable.
+    /// If the file is unknown the client gets an error response.
+    pub(crate) fn send_peer_list(
+        &mut self,
+        message: &RequestPeerList,
+        addressee_srh: &SourceRoutingHeader,
+        session_id: SessionIdT,
+    ) {
+        // Retrieve the requested file
+        let file_peers = match self.database.get_client_type(message.client_id) {
+            Ok(ClientType::Song) => self
+                .database
+                .get_song_entry(message.file_hash)
+                .map(|entry| entry.peers),
+            Ok(ClientType::Video) => self
+                .database
+                .get_video_entry(message.file_hash)
+                .map(|entry| entry.peers),
+            Err(err) => Err(err),
+        };
+
+        let file_peers = match file_peers {
+            Ok(peers) => peers,
+            Err(err) => {
+                self.logger.log_error(&err.to_string());
+                let err = ServerError::from(err);
+                self.send_error_response(message.client_id, session_id, &err, addressee_srh);
+                return;
+            }
+        };
+
+        // Create the vector to send to the client
+        let peers_info = self.rank_peers(&file_peers, message.client_id);
 
         // Create response
         let file_list = ResponsePeerList::new(message.file_hash, peers_info);
@@ -290,8 +654,8 @@ impl Server {
         };
 
         let next_hop = srh.hops[srh.hop_index];
-        if let Err(msg) = self.send_save_packets(&packets, next_hop) {
-            self.logger.log_error(&msg);
+        if let Err(err) = self.send_save_packets(&packets, next_hop) {
+            self.logger.log_error(&err.to_string());
             return;
         }
 
@@ -299,42 +663,70 @@ impl Server {
             .log_info("[RESPONSE PEER LIST] Sent successfully!");
     }
 
-    /// Unsubscribe the information of a client
-    pub(crate) fn unsubscribe_client(&mut self, message: &UnsubscribeClient) {
-        // Check if client is subscribed
-        if !self.database.contains_client(message.client_id) {
+    /// Renew the lease of the client. Any packet renews it, the message only has to be acknowledged.
+    pub(crate) fn handle_heartbeat(&mut self, message: &Heartbeat) {
+        if self.refresh_lease(message.client_id) {
+            self.logger.log_debug(&format!(
+                "[HEARTBEAT] - Lease of [CLIENT-{}] renewed",
+                message.client_id
+            ));
+        } else {
             self.logger.log_warn(&format!(
-                "Received UnsubscribeClient for [CLIENT-{}] but no client was found.",
+                "Received Heartbeat for [CLIENT-{}] but no client was found.",
                 message.client_id
             ));
+        }
+    }
+
+    /// Unsubscribe the information of a client, on its request or when its lease expires
+    pub(crate) fn unsubscribe_client(&mut self, client_id: NodeId, reason: UnsubscribeReason) {
+        // Check if client is subscribed
+        if !self.database.contains_client(client_id) {
+            self.logger.log_warn(&match reason {
+                UnsubscribeReason::Requested => format!(
+                    "Received UnsubscribeClient for [CLIENT-{client_id}] but no client was found."
+                ),
+                UnsubscribeReason::LeaseExpired => format!(
+                    "[LEASE] - Lease of [CLIENT-{client_id}] expired but no client was found."
+                ),
+            });
             return;
         }
 
-        // Remove Client from clients HashMap
-        let Ok(client_type) = self.database.remove_client(message.client_id) else {
+        // Remove Client from clients_tree
+        let Ok(client_info) = self.database.remove_client(client_id) else {
             self.logger.log_error(&format!(
-                "No [CLIENT {}] found: could not remove it from clients!",
-                message.client_id
+                "No [CLIENT {client_id}] found: could not remove it from clients!"
             ));
             return;
         };
 
-        let res = match client_type {
-            Some(ClientType::Song) => self.database.remove_peer_from_songs(message.client_id),
-            Some(ClientType::Video) => self.database.remove_peer_from_videos(message.client_id),
-            None => Err(format!(
-                "Remove client returned with None. No [CLIENT-{}] removed.",
-                message.client_id
-            )),
+        // Only the entries of the files shared by the client are touched
+        let res = match client_info.map(|info| info.client_type) {
+            Some(ClientType::Song) => self.database.remove_peer_from_songs(client_id),
+            Some(ClientType::Video) => self.database.remove_peer_from_videos(client_id),
+            None => Err(DatabaseError::ClientNotFound(client_id)),
         };
 
-        if let Err(msg) = res {
-            self.logger.log_error(&msg);
+        if let Err(err) = res {
+            self.logger.log_error(&err.to_string());
         }
 
-        self.logger.log_info(&format!(
-            "Client-{} unsubscribed with success!",
-            message.client_id
-        ));
+        if let Err(err) = self.database.clear_quarantine(client_id) {
+            self.logger.log_error(&err.to_string());
+        }
+
+        self.client_leases.revoke(client_id);
+        self.catalog_subscribers.remove(client_id);
+        self.schedule_catalog_push();
+
+        match reason {
+            UnsubscribeReason::Requested => self
+                .logger
+                .log_info(&format!("Client-{client_id} unsubscribed with success!")),
+            UnsubscribeReason::LeaseExpired => self.logger.log_warn(&format!(
+                "[LEASE] - Lease of [CLIENT-{client_id}] expired, client unsubscribed"
+            )),
+        }
     }
 }
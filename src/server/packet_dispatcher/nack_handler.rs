@@ -9,8 +9,42 @@ use wg_internal::{
 };
 
 impl Server {
+    /// Tell the source of `packet` that its fragment has not been processed.
+    /// The `Nack` follows the best known path, the received routing header may be malformed.
+    pub(crate) fn send_nack(&mut self, packet: &Packet, fragment_index: u64, nack_type: NackType) {
+        let Some(&source) = packet.routing_header.hops.first() else {
+            self.logger.log_error(&format!(
+                "[NACK] Unable to send {nack_type:?} for [ ({fragment_index}, {}) ]: unknown source",
+                packet.session_id
+            ));
+            return;
+        };
+        let Some(srh) = self.get_path(self.id, source) else {
+            self.logger.log_error(&format!(
+                "[NACK] Unable to send {nack_type:?} for [ ({fragment_index}, {}) ]: no path to [NODE-{source}]",
+                packet.session_id
+            ));
+            return;
+        };
+
+        let next_hop = srh.hops[srh.hop_index];
+        let nack = Packet::new_nack(
+            srh,
+            packet.session_id,
+            Nack {
+                fragment_index,
+                nack_type,
+            },
+        );
+
+        if let Err(err) = self.send_packets_vec(&[nack], next_hop) {
+            self.logger.log_error(&err.to_string());
+        }
+    }
+
     /// This function retransmit the packet for which the server received the Nack and tries to calculate a new optimal path.
-    fn retransmit_packet(
+    /// On success the history entry is refreshed with the new route and send time.
+    pub(crate) fn retransmit_packet(
         &mut self,
         packet: &mut Packet,
         fragment_index: u64,
@@ -32,11 +66,14 @@ impl Server {
         // Assign the new SourceRoutingHeader
         packet.routing_header = srh;
 
-        if let Err(msg) = self.send_packets_vec(&[packet.clone()], next_hop) {
-            self.logger.log_error(&msg);
+        if let Err(err) = self.send_packets_vec(&[packet.clone()], next_hop) {
+            self.logger.log_error(&err.to_string());
             return;
         }
 
+        self.sent_fragments_history
+            .refresh(fragment_index, session_id, packet.clone());
+
         self.logger.log_info(&format!(
             "[RETRANSMIT PACKET] Successfully sent packet [ ({fragment_index}, {session_id}) ]"
         ));
@@ -52,8 +89,8 @@ impl Server {
         // Retrieve the packet that generated the nack
         let Some(mut packet) = self
             .sent_fragments_history
-            .get(&(message.fragment_index, session_id))
-            .cloned()
+            .get(message.fragment_index, session_id)
+            .map(|entry| entry.packet.clone())
         else {
             self.logger.log_error(&format!(
                 "[NACK] Failed to retrieve packet with [ ({}, {}) ] key from packet history",
@@ -66,6 +103,11 @@ impl Server {
             NackType::Dropped => {
                 // Update graph heuristic
                 self.routing_handler.node_nack(source_node_id);
+                self.link_costs.on_nack(source_node_id);
+                // Slow down the sessions towards the same client
+                if let Some(destination) = Self::destination(&packet) {
+                    self.window_on_drop(destination);
+                }
                 self.retransmit_packet(&mut packet, message.fragment_index, session_id);
             }
             NackType::DestinationIsDrone => {
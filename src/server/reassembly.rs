@@ -0,0 +1,204 @@
+use packet_forge::SessionIdT;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Fragment;
+
+/// Default time after which a partially received message is discarded
+pub(crate) const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of adding a `Fragment` to the `ReassemblyBuffer`
+#[derive(Debug)]
+pub(crate) enum FragmentOutcome {
+    /// The fragment has been stored, the message still misses some fragments
+    Incomplete,
+    /// The fragment was already received or its message was already delivered
+    Duplicate,
+    /// The fragment does not fit the message it belongs to
+    Invalid(String),
+    /// Every fragment of the message has been received, they are returned ordered by index
+    Complete(Vec<Fragment>),
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    total_n_fragments: u64,
+    fragments: BTreeMap<u64, Fragment>, // fragment_index -> Fragment
+    last_update: Instant,
+}
+
+/// Collects the fragments of the incoming messages, keyed by (`client_id`, `session_id`).
+/// Sessions are freed once delivered and discarded if they are not completed within the timeout.
+#[derive(Debug)]
+pub(crate) struct ReassemblyBuffer {
+    sessions: HashMap<(NodeId, SessionIdT), PartialMessage>,
+    // Recently delivered sessions, to recognise late duplicates
+    delivered: HashMap<(NodeId, SessionIdT), Instant>,
+    timeout: Duration,
+}
+
+impl ReassemblyBuffer {
+    pub fn new(timeout: Duration) -> Self {
+        ReassemblyBuffer {
+            sessions: HashMap::new(),
+            delivered: HashMap::new(),
+            timeout,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Store `fragment` in the session identified by `key`.
+    pub fn insert(&mut self, key: (NodeId, SessionIdT), fragment: &Fragment) -> FragmentOutcome {
+        if self.delivered.contains_key(&key) {
+            return FragmentOutcome::Duplicate;
+        }
+
+        if fragment.fragment_index >= fragment.total_n_fragments {
+            return FragmentOutcome::Invalid(format!(
+                "Fragment index {} out of range, total fragments: {}",
+                fragment.fragment_index, fragment.total_n_fragments
+            ));
+        }
+
+        let now = Instant::now();
+        let message = self.sessions.entry(key).or_insert_with(|| PartialMessage {
+            total_n_fragments: fragment.total_n_fragments,
+            fragments: BTreeMap::new(),
+            last_update: now,
+        });
+
+        if message.total_n_fragments != fragment.total_n_fragments {
+            return FragmentOutcome::Invalid(format!(
+                "Total fragments mismatch: expected {}, received {}",
+                message.total_n_fragments, fragment.total_n_fragments
+            ));
+        }
+
+        if message.fragments.contains_key(&fragment.fragment_index) {
+            return FragmentOutcome::Duplicate;
+        }
+
+        message
+            .fragments
+            .insert(fragment.fragment_index, fragment.clone());
+        message.last_update = now;
+
+        if message.fragments.len() as u64 != message.total_n_fragments {
+            return FragmentOutcome::Incomplete;
+        }
+
+        // Free the session and remember it was delivered
+        let Some(message) = self.sessions.remove(&key) else {
+            return FragmentOutcome::Incomplete;
+        };
+        self.delivered.insert(key, now);
+        FragmentOutcome::Complete(message.fragments.into_values().collect())
+    }
+
+    /// Instant at which the oldest tracked session expires
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .map(|message| message.last_update)
+            .chain(self.delivered.values().copied())
+            .min()
+            .map(|last_update| last_update + self.timeout)
+    }
+
+    /// Discard the partial messages that have not received fragments within the timeout.
+    /// Returns the keys of the discarded messages with the number of fragments received.
+    pub fn expire(&mut self, now: Instant) -> Vec<((NodeId, SessionIdT), usize)> {
+        let timeout = self.timeout;
+        self.delivered
+            .retain(|_, delivered_at| *delivered_at + timeout > now);
+
+        let expired: Vec<((NodeId, SessionIdT), usize)> = self
+            .sessions
+            .iter()
+            .filter(|(_, message)| message.last_update + timeout <= now)
+            .map(|(key, message)| (*key, message.fragments.len()))
+            .collect();
+
+        for (key, _) in &expired {
+            self.sessions.remove(key);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::fragment;
+
+    #[test]
+    fn completes_out_of_order() {
+        let mut buffer = ReassemblyBuffer::new(DEFAULT_REASSEMBLY_TIMEOUT);
+        let key = (1, 7);
+
+        assert!(matches!(
+            buffer.insert(key, &fragment(1, 2)),
+            FragmentOutcome::Incomplete
+        ));
+        let FragmentOutcome::Complete(fragments) = buffer.insert(key, &fragment(0, 2)) else {
+            panic!("message should be complete");
+        };
+        let indexes: Vec<u64> = fragments.iter().map(|f| f.fragment_index).collect();
+        assert_eq!(indexes, vec![0, 1]);
+    }
+
+    #[test]
+    fn rejects_invalid_and_duplicate_fragments() {
+        let mut buffer = ReassemblyBuffer::new(DEFAULT_REASSEMBLY_TIMEOUT);
+        let key = (1, 7);
+
+        assert!(matches!(
+            buffer.insert(key, &fragment(2, 2)),
+            FragmentOutcome::Invalid(_)
+        ));
+        buffer.insert(key, &fragment(0, 3));
+        assert!(matches!(
+            buffer.insert(key, &fragment(1, 2)),
+            FragmentOutcome::Invalid(_)
+        ));
+        assert!(matches!(
+            buffer.insert(key, &fragment(0, 3)),
+            FragmentOutcome::Duplicate
+        ));
+    }
+
+    #[test]
+    fn delivered_sessions_are_emptied_on_expiry() {
+        let mut buffer = ReassemblyBuffer::new(Duration::from_secs(1));
+        let key = (1, 7);
+
+        buffer.insert(key, &fragment(0, 1));
+        assert!(matches!(
+            buffer.insert(key, &fragment(0, 1)),
+            FragmentOutcome::Duplicate
+        ));
+        let deadline = buffer.next_expiry().expect("delivered session is tracked");
+
+        assert!(buffer.expire(deadline).is_empty());
+        assert!(buffer.delivered.is_empty());
+        assert_eq!(buffer.next_expiry(), None);
+    }
+
+    #[test]
+    fn discards_stale_partial_messages() {
+        let mut buffer = ReassemblyBuffer::new(Duration::from_secs(1));
+        let key = (1, 7);
+
+        buffer.insert(key, &fragment(0, 2));
+        let deadline = buffer.next_expiry().expect("partial message is tracked");
+
+        assert!(buffer
+            .expire(deadline - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(buffer.expire(deadline), vec![(key, 1)]);
+        assert!(buffer.sessions.is_empty());
+    }
+}
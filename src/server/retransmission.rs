@@ -0,0 +1,110 @@
+use super::scheduler::Task;
+use super::{Server, ServerEvent};
+
+use packet_forge::SessionIdT;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use wg_internal::packet::Packet;
+
+/// Time to wait for an `Ack` before the first retransmission of a fragment
+pub(crate) const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Number of timeout-driven retransmissions after which the session is abandoned
+pub(crate) const MAX_RETRANSMISSIONS: u32 = 5;
+
+/// A fragment waiting for its `Ack`
+#[derive(Debug, Clone)]
+pub(crate) struct SentFragment {
+    pub packet: Packet,
+    pub sent_at: Instant,
+    pub retries: u32,
+}
+
+impl SentFragment {
+    pub fn new(packet: Packet) -> Self {
+        SentFragment {
+            packet,
+            sent_at: Instant::now(),
+            retries: 0,
+        }
+    }
+
+    /// Instant after which the fragment is considered lost. The timeout doubles at each retry.
+    pub fn deadline(&self) -> Instant {
+        self.sent_at + RETRANSMIT_TIMEOUT.saturating_mul(1 << self.retries.min(16))
+    }
+}
+
+impl Server {
+    /// Schedule the next retransmission check at the earliest fragment deadline.
+    pub(crate) fn schedule_retransmission_check(&mut self) {
+        if let Some(deadline) = self
+            .sent_fragments_history
+            .iter()
+            .map(|(_, entry)| entry.deadline())
+            .min()
+        {
+            self.scheduler.schedule(Task::Retransmission, deadline);
+        }
+    }
+
+    /// Retransmit every fragment whose `Ack` did not arrive in time.
+    /// Sessions with a fragment that exceeded `MAX_RETRANSMISSIONS` are abandoned.
+    pub(crate) fn check_retransmissions(&mut self) {
+        let now = Instant::now();
+        let mut expired: Vec<(u64, SessionIdT)> = self
+            .sent_fragments_history
+            .iter()
+            .filter(|(_, entry)| entry.deadline() <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        expired.sort_unstable();
+
+        let mut abandoned: HashSet<SessionIdT> = HashSet::new();
+        for (fragment_index, session_id) in expired {
+            if abandoned.contains(&session_id) {
+                continue;
+            }
+            let Some(entry) = self
+                .sent_fragments_history
+                .get_mut(fragment_index, session_id)
+            else {
+                continue;
+            };
+
+            if entry.retries >= MAX_RETRANSMISSIONS {
+                let packet = entry.packet.clone();
+                abandoned.insert(session_id);
+                self.abandon_session(session_id, &packet);
+                continue;
+            }
+
+            entry.retries += 1;
+            entry.sent_at = now;
+            let mut packet = entry.packet.clone();
+            self.logger.log_warn(&format!(
+                "[RETRANSMIT PACKET] No Ack received for [ ({fragment_index}, {session_id}) ], retry {}/{MAX_RETRANSMISSIONS}",
+                entry.retries
+            ));
+            self.retransmit_packet(&mut packet, fragment_index, session_id);
+        }
+
+        self.schedule_retransmission_check();
+    }
+
+    /// Drop every pending fragment of `session_id` and notify the Simulation Controller
+    /// that the session, of which `packet` is the fragment that exhausted its retransmissions, could not be delivered.
+    pub(crate) fn abandon_session(&mut self, session_id: SessionIdT, packet: &Packet) {
+        self.sent_fragments_history.remove_session(session_id);
+        self.window_discard(session_id);
+
+        self.logger.log_error(&format!(
+            "[RETRANSMIT PACKET] Giving up on session {session_id} after {MAX_RETRANSMISSIONS} retransmissions of {packet}"
+        ));
+        if let Some(destination) = Self::destination(packet) {
+            self.report_event(ServerEvent::SessionAbandoned {
+                destination,
+                session_id,
+            });
+        }
+    }
+}
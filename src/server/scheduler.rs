@@ -0,0 +1,163 @@
+use super::Server;
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+/// Interval between two periodic flood requests
+pub(crate) const FLOOD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Work that the server must perform at a given moment rather than in response to a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Task {
+    /// Refresh the network topology with a new flood request
+    Flooding,
+    /// Resend the fragments whose `Ack` did not arrive in time
+    Retransmission,
+    /// Drop the sent sessions that are never going to be acknowledged
+    HistoryExpiry,
+    /// Discard the incoming messages that are never going to be completed
+    ReassemblyExpiry,
+    /// Send the pending file list changes to the subscribed clients
+    CatalogPush,
+    /// Unsubscribe the clients whose lease has expired
+    LeaseExpiry,
+    /// Drop the quarantined files kept for too long
+    QuarantineExpiry,
+}
+
+/// Keeps track of the timed tasks of the server.
+/// Each task can be scheduled at most once: scheduling it again moves its deadline.
+#[derive(Debug, Default)]
+pub(crate) struct Scheduler {
+    queue: BTreeSet<(Instant, Task)>,
+    deadlines: HashMap<Task, Instant>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedule `task` at `deadline`, replacing any previous deadline for the same task.
+    pub fn schedule(&mut self, task: Task, deadline: Instant) {
+        if let Some(old_deadline) = self.deadlines.insert(task, deadline) {
+            self.queue.remove(&(old_deadline, task));
+        }
+        self.queue.insert((deadline, task));
+    }
+
+    /// Schedule `task` at `deadline` unless it is already scheduled earlier.
+    pub fn schedule_no_later(&mut self, task: Task, deadline: Instant) {
+        match self.deadlines.get(&task) {
+            Some(current) if *current <= deadline => {}
+            _ => self.schedule(task, deadline),
+        }
+    }
+
+    /// Returns the deadline of the next task to run, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.first().map(|(deadline, _)| *deadline)
+    }
+
+    /// Remove and return, in deadline order, all the tasks whose deadline is before `now`.
+    pub fn pop_due(&mut self, now: Instant) -> Vec<Task> {
+        let mut due = Vec::new();
+        while let Some(&(deadline, task)) = self.queue.first() {
+            if deadline > now {
+                break;
+            }
+            self.queue.pop_first();
+            self.deadlines.remove(&task);
+            due.push(task);
+        }
+        due
+    }
+}
+
+impl Server {
+    /// Run every scheduled task whose deadline has expired.
+    pub(crate) fn run_scheduled_tasks(&mut self) {
+        for task in self.scheduler.pop_due(Instant::now()) {
+            self.logger
+                .log_debug(&format!("[SCHEDULER] - Running task {task:?}"));
+            match task {
+                Task::Flooding => {
+                    // Another flood may have been started in the meantime (e.g. topology change)
+                    if self.flood_countdown.elapsed() >= FLOOD_INTERVAL {
+                        self.init_flood_request();
+                    }
+                    self.scheduler
+                        .schedule(Task::Flooding, self.flood_countdown + FLOOD_INTERVAL);
+                }
+                Task::Retransmission => self.check_retransmissions(),
+                Task::HistoryExpiry => self.expire_packet_history(),
+                Task::ReassemblyExpiry => self.expire_reassembly_buffer(),
+                Task::CatalogPush => self.push_catalog_changes(),
+                Task::LeaseExpiry => self.expire_client_leases(),
+                Task::QuarantineExpiry => self.expire_quarantine(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_due_tasks_in_deadline_order() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Task::Retransmission, now + Duration::from_secs(2));
+        scheduler.schedule(Task::Flooding, now + Duration::from_secs(1));
+        scheduler.schedule(Task::HistoryExpiry, now + Duration::from_secs(5));
+
+        assert_eq!(
+            scheduler.next_deadline(),
+            Some(now + Duration::from_secs(1))
+        );
+        assert!(scheduler.pop_due(now).is_empty());
+        assert_eq!(
+            scheduler.pop_due(now + Duration::from_secs(2)),
+            vec![Task::Flooding, Task::Retransmission]
+        );
+        assert_eq!(
+            scheduler.next_deadline(),
+            Some(now + Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn rescheduling_moves_the_deadline() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Task::Flooding, now + Duration::from_secs(1));
+        scheduler.schedule(Task::Flooding, now + Duration::from_secs(3));
+
+        assert!(scheduler.pop_due(now + Duration::from_secs(2)).is_empty());
+        assert_eq!(
+            scheduler.pop_due(now + Duration::from_secs(3)),
+            vec![Task::Flooding]
+        );
+        assert_eq!(scheduler.next_deadline(), None);
+    }
+
+    #[test]
+    fn schedule_no_later_keeps_the_earliest_deadline() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule_no_later(Task::CatalogPush, now + Duration::from_secs(2));
+        scheduler.schedule_no_later(Task::CatalogPush, now + Duration::from_secs(4));
+        assert_eq!(
+            scheduler.next_deadline(),
+            Some(now + Duration::from_secs(2))
+        );
+
+        scheduler.schedule_no_later(Task::CatalogPush, now + Duration::from_secs(1));
+        assert_eq!(
+            scheduler.next_deadline(),
+            Some(now + Duration::from_secs(1))
+        );
+        assert_eq!(scheduler.pop_due(now + Duration::from_secs(4)).len(), 1);
+    }
+}
@@ -0,0 +1,290 @@
+use super::retransmission::SentFragment;
+
+use packet_forge::SessionIdT;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// Default amount of memory that the sent fragments may occupy (64 MiB)
+pub(crate) const DEFAULT_HISTORY_BUDGET: usize = 64 * 1024 * 1024;
+/// Default time after which a session without any `Ack` is dropped
+pub(crate) const DEFAULT_SESSION_EXPIRY: Duration = Duration::from_secs(60);
+
+/// Approximate memory footprint of a stored packet
+fn packet_size(packet: &Packet) -> usize {
+    size_of::<SentFragment>() + packet.routing_header.hops.len() * size_of::<NodeId>()
+}
+
+#[derive(Debug)]
+struct SessionHistory {
+    fragments: HashMap<u64, SentFragment>, // fragment_index -> SentFragment
+    bytes: usize,
+    last_activity: Instant,
+}
+
+/// Fragments sent by the server and not acknowledged yet, grouped by session.
+/// - the total memory is bounded by a byte budget: when exceeded the least recently active sessions are evicted
+/// - sessions that stay idle for longer than the expiry time are dropped by `expire`
+#[derive(Debug)]
+pub(crate) struct SentHistory {
+    sessions: HashMap<SessionIdT, SessionHistory>,
+    memory_usage: usize,
+    byte_budget: usize,
+    session_expiry: Duration,
+}
+
+impl SentHistory {
+    pub fn new(byte_budget: usize, session_expiry: Duration) -> Self {
+        SentHistory {
+            sessions: HashMap::new(),
+            memory_usage: 0,
+            byte_budget,
+            session_expiry,
+        }
+    }
+
+    pub fn set_byte_budget(&mut self, byte_budget: usize) {
+        self.byte_budget = byte_budget;
+    }
+
+    pub fn set_session_expiry(&mut self, session_expiry: Duration) {
+        self.session_expiry = session_expiry;
+    }
+
+    /// Bytes currently held by the stored fragments
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    pub fn get(&self, fragment_index: u64, session_id: SessionIdT) -> Option<&SentFragment> {
+        self.sessions
+            .get(&session_id)
+            .and_then(|session| session.fragments.get(&fragment_index))
+    }
+
+    pub fn get_mut(
+        &mut self,
+        fragment_index: u64,
+        session_id: SessionIdT,
+    ) -> Option<&mut SentFragment> {
+        self.sessions
+            .get_mut(&session_id)
+            .and_then(|session| session.fragments.get_mut(&fragment_index))
+    }
+
+    /// Iterate over every stored fragment with its `(fragment_index, session_id)` key
+    pub fn iter(&self) -> impl Iterator<Item = ((u64, SessionIdT), &SentFragment)> {
+        self.sessions.iter().flat_map(|(session_id, session)| {
+            session
+                .fragments
+                .iter()
+                .map(move |(fragment_index, entry)| ((*fragment_index, *session_id), entry))
+        })
+    }
+
+    /// Returns true if a session made of `packets` fits the byte budget on its own.
+    /// Larger sessions must not be sent, they could never be retransmitted.
+    pub fn fits(&self, packets: &[Packet]) -> bool {
+        packets.iter().map(packet_size).sum::<usize>() <= self.byte_budget
+    }
+
+    /// Store a sent fragment.
+    /// Returns the sessions evicted to stay within the byte budget. The session of the inserted fragment is never evicted,
+    /// `fits` must be checked before sending it.
+    pub fn insert(
+        &mut self,
+        fragment_index: u64,
+        session_id: SessionIdT,
+        entry: SentFragment,
+    ) -> Vec<SessionIdT> {
+        let size = packet_size(&entry.packet);
+        let session = self
+            .sessions
+            .entry(session_id)
+            .or_insert_with(|| SessionHistory {
+                fragments: HashMap::new(),
+                bytes: 0,
+                last_activity: Instant::now(),
+            });
+
+        session.last_activity = Instant::now();
+        session.bytes += size;
+        self.memory_usage += size;
+        if let Some(old) = session.fragments.insert(fragment_index, entry) {
+            let old_size = packet_size(&old.packet);
+            session.bytes -= old_size;
+            self.memory_usage -= old_size;
+        }
+
+        self.evict(session_id)
+    }
+
+    /// Replace the stored packet (e.g. after a re-routing) and reset its send time.
+    pub fn refresh(&mut self, fragment_index: u64, session_id: SessionIdT, packet: Packet) {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+        let Some(entry) = session.fragments.get_mut(&fragment_index) else {
+            return;
+        };
+
+        let (old_size, new_size) = (packet_size(&entry.packet), packet_size(&packet));
+        session.bytes = session.bytes - old_size + new_size;
+        self.memory_usage = self.memory_usage - old_size + new_size;
+        entry.packet = packet;
+        entry.sent_at = Instant::now();
+    }
+
+    /// Remove an acknowledged fragment. The session is freed once all its fragments are removed.
+    pub fn remove(&mut self, fragment_index: u64, session_id: SessionIdT) -> Option<SentFragment> {
+        let session = self.sessions.get_mut(&session_id)?;
+        let entry = session.fragments.remove(&fragment_index)?;
+
+        let size = packet_size(&entry.packet);
+        session.bytes -= size;
+        session.last_activity = Instant::now();
+        self.memory_usage -= size;
+
+        if session.fragments.is_empty() {
+            self.sessions.remove(&session_id);
+        }
+        Some(entry)
+    }
+
+    /// Drop all the fragments of a session, returns how many were removed.
+    pub fn remove_session(&mut self, session_id: SessionIdT) -> usize {
+        let Some(session) = self.sessions.remove(&session_id) else {
+            return 0;
+        };
+        self.memory_usage -= session.bytes;
+        session.fragments.len()
+    }
+
+    /// Instant at which the least recently active session expires
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .map(|session| session.last_activity + self.session_expiry)
+            .min()
+    }
+
+    /// Drop the sessions that had no activity during the expiry time, returns their ids.
+    pub fn expire(&mut self, now: Instant) -> Vec<SessionIdT> {
+        let expired: Vec<SessionIdT> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.last_activity + self.session_expiry <= now)
+            .map(|(session_id, _)| *session_id)
+            .collect();
+
+        for session_id in &expired {
+            self.remove_session(*session_id);
+        }
+        expired
+    }
+
+    /// Evict whole sessions, least recently active first, until the history fits the byte budget.
+    fn evict(&mut self, protected: SessionIdT) -> Vec<SessionIdT> {
+        let mut evicted = Vec::new();
+        while self.memory_usage > self.byte_budget {
+            let Some(oldest) = self
+                .sessions
+                .iter()
+                .filter(|(session_id, _)| **session_id != protected)
+                .min_by_key(|(_, session)| session.last_activity)
+                .map(|(session_id, _)| *session_id)
+            else {
+                break;
+            };
+            self.remove_session(oldest);
+            evicted.push(oldest);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::fragment_packet;
+
+    fn packet(session_id: SessionIdT, fragment_index: u64) -> Packet {
+        fragment_packet(session_id, fragment_index, 4)
+    }
+
+    fn insert(
+        history: &mut SentHistory,
+        session_id: SessionIdT,
+        fragment_index: u64,
+    ) -> Vec<SessionIdT> {
+        history.insert(
+            fragment_index,
+            session_id,
+            SentFragment::new(packet(session_id, fragment_index)),
+        )
+    }
+
+    #[test]
+    fn tracks_memory_usage() {
+        let size = packet_size(&packet(1, 0));
+        let mut history = SentHistory::new(DEFAULT_HISTORY_BUDGET, DEFAULT_SESSION_EXPIRY);
+
+        insert(&mut history, 1, 0);
+        insert(&mut history, 1, 1);
+        assert_eq!(history.memory_usage(), 2 * size);
+
+        // Re-inserting the same fragment replaces it
+        insert(&mut history, 1, 1);
+        assert_eq!(history.memory_usage(), 2 * size);
+
+        assert!(history.remove(1, 1).is_some());
+        assert!(history.remove(1, 1).is_none());
+        assert_eq!(history.remove_session(1), 1);
+        assert_eq!(history.memory_usage(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recent_sessions_but_the_inserted_one() {
+        let size = packet_size(&packet(1, 0));
+        let mut history = SentHistory::new(2 * size, DEFAULT_SESSION_EXPIRY);
+
+        assert!(insert(&mut history, 1, 0).is_empty());
+        assert!(insert(&mut history, 2, 0).is_empty());
+        // Make the activity order explicit
+        let now = Instant::now();
+        for (session_id, age) in [(1, 2), (2, 1)] {
+            if let Some(session) = history.sessions.get_mut(&session_id) {
+                session.last_activity = now - Duration::from_secs(age);
+            }
+        }
+        assert_eq!(insert(&mut history, 3, 0), vec![1]);
+        assert_eq!(insert(&mut history, 3, 1), vec![2]);
+        assert!(history.get(0, 3).is_some());
+        assert_eq!(history.memory_usage(), 2 * size);
+    }
+
+    #[test]
+    fn sessions_larger_than_the_budget_do_not_fit() {
+        let size = packet_size(&packet(1, 0));
+        let history = SentHistory::new(2 * size, DEFAULT_SESSION_EXPIRY);
+
+        assert!(history.fits(&[packet(1, 0), packet(1, 1)]));
+        assert!(!history.fits(&[packet(1, 0), packet(1, 1), packet(1, 2)]));
+    }
+
+    #[test]
+    fn expires_idle_sessions() {
+        let mut history = SentHistory::new(DEFAULT_HISTORY_BUDGET, Duration::from_secs(1));
+        insert(&mut history, 1, 0);
+        let deadline = history.next_expiry().expect("session is tracked");
+
+        assert!(history
+            .expire(deadline - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(history.expire(deadline), vec![1]);
+        assert_eq!(history.memory_usage(), 0);
+        assert_eq!(history.next_expiry(), None);
+    }
+}
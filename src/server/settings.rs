@@ -0,0 +1,52 @@
+use super::{Server, ValidationPolicy};
+use crate::database::CatalogMode;
+
+use std::time::Duration;
+
+/* SERVER SETTINGS */
+impl Server {
+    /// Set the maximum amount of bytes that the unacknowledged fragments may occupy
+    pub fn with_history_budget(&mut self, bytes: usize) {
+        self.sent_fragments_history.set_byte_budget(bytes);
+    }
+
+    /// Set after how long a sent session with no `Ack` is dropped from the history
+    pub fn with_history_expiry(&mut self, expiry: Duration) {
+        self.sent_fragments_history.set_session_expiry(expiry);
+    }
+
+    /// Set how many of the best peers are sent for a requested file
+    pub fn with_peer_list_size(&mut self, size: usize) {
+        self.peer_list_size = size;
+    }
+
+    /// Keep the database content across restarts, only new or changed local files are ingested
+    pub fn with_persistent_catalog(&mut self) {
+        self.catalog_mode = CatalogMode::Persistent;
+    }
+
+    /// Set after how long a client that sends no packet nor `Heartbeat` is unsubscribed
+    pub fn with_client_lease(&mut self, duration: Duration) {
+        self.client_leases.set_duration(duration);
+    }
+
+    /// Set the minimum interval between two rounds of file list updates sent to the subscribed clients
+    pub fn with_catalog_push_interval(&mut self, interval: Duration) {
+        self.catalog_subscribers.set_interval(interval);
+    }
+
+    /// Set how the files submitted by the clients with a wrong `FileHash` are handled
+    pub fn with_validation_policy(&mut self, policy: ValidationPolicy) {
+        self.validation_policy = policy;
+    }
+
+    /// Set after how long a quarantined file that the client has not fixed is dropped
+    pub fn with_quarantine_expiry(&mut self, expiry: Duration) {
+        self.quarantine_expiry = expiry;
+    }
+
+    /// Set after how long an incomplete incoming message is discarded
+    pub fn with_reassembly_timeout(&mut self, timeout: Duration) {
+        self.reassembly_buffer.set_timeout(timeout);
+    }
+}
@@ -1,11 +1,16 @@
 use crate::{
+    error::ServerError,
     packet_send::{get_sender, sc_send_packet, send_packet},
     utils::get_packet_type,
 };
 
+use super::retransmission::{SentFragment, RETRANSMIT_TIMEOUT};
+use super::scheduler::Task;
 use super::Server;
 
-use packet_forge::{FileHash, Metadata};
+use packet_forge::{FileHash, Message, Metadata};
+use std::fmt::Debug;
+use std::time::Instant;
 use wg_internal::{
     controller::DroneEvent,
     network::{NodeId, SourceRoutingHeader},
@@ -15,17 +20,17 @@ use wg_internal::{
 impl Server {
     /// Check if the received `file_hash` is correct.
     /// ### Error
-    /// Log the two mismatched hash
+    /// Returns the two mismatched hash
     pub(crate) fn check_hash<M: Metadata>(
         file_hash: FileHash,
         file_metadata: &M,
-    ) -> Result<(), String> {
-        if file_hash != file_metadata.compact_hash_u16() {
-            return Err(format!(
-                "File hash mismatch! Received: [ {:?} ]\nCalculated: [ {:?} ]",
-                file_hash,
-                file_metadata.compact_hash_u16()
-            ));
+    ) -> Result<(), ServerError> {
+        let calculated = file_metadata.compact_hash_u16();
+        if file_hash != calculated {
+            return Err(ServerError::HashMismatch {
+                received: file_hash,
+                calculated,
+            });
         }
         Ok(())
     }
@@ -43,8 +48,44 @@ impl Server {
                 continue;
             };
 
-            let key = (fragment.fragment_index, p.session_id);
-            self.sent_fragments_history.insert(key, p.clone());
+            let evicted = self.sent_fragments_history.insert(
+                fragment.fragment_index,
+                p.session_id,
+                SentFragment::new(p.clone()),
+            );
+            for session_id in evicted {
+                self.window_discard(session_id);
+                self.logger.log_warn(&format!(
+                    "[PACKET HISTORY] Memory budget exceeded, evicted session {session_id}"
+                ));
+            }
+        }
+
+        self.logger.log_debug(&format!(
+            "[PACKET HISTORY] Memory usage: {} bytes",
+            self.sent_fragments_history.memory_usage()
+        ));
+
+        // Make sure the retransmission and expiry timers cover the new fragments
+        self.scheduler
+            .schedule_no_later(Task::Retransmission, Instant::now() + RETRANSMIT_TIMEOUT);
+        if let Some(deadline) = self.sent_fragments_history.next_expiry() {
+            self.scheduler
+                .schedule_no_later(Task::HistoryExpiry, deadline);
+        }
+    }
+
+    /// Drop the sent sessions that have not been acknowledged for too long.
+    pub(crate) fn expire_packet_history(&mut self) {
+        for session_id in self.sent_fragments_history.expire(Instant::now()) {
+            self.window_discard(session_id);
+            self.logger.log_warn(&format!(
+                "[PACKET HISTORY] Session {session_id} expired without being acknowledged"
+            ));
+        }
+
+        if let Some(deadline) = self.sent_fragments_history.next_expiry() {
+            self.scheduler.schedule(Task::HistoryExpiry, deadline);
         }
     }
 
@@ -54,11 +95,53 @@ impl Server {
             Some(srh)
         } else {
             self.logger
-                .log_error(&format!("No path found from {} to {}!", self.id, to));
+                .log_error(&format!("No path found from {from} to {to}!"));
             None
         }
     }
 
+    /// Send `message` to `client_id` through the best path, or through `fallback_srh`
+    /// (usually the reversed path of the request) if no path is found.
+    /// `tag` prefixes the log lines, e.g. `"RESPONSE FILE LIST PAGE"`.
+    /// ### Error
+    /// Returns `ServerError::NoRoute` if no path is found and there is no fallback,
+    /// otherwise the error of disassembling or sending the packets.
+    pub(crate) fn send_message<M: Message + Clone + Debug>(
+        &mut self,
+        client_id: NodeId,
+        message: M,
+        fallback_srh: Option<&SourceRoutingHeader>,
+        tag: &str,
+    ) -> Result<(), ServerError> {
+        let srh = match (self.get_path(self.id, client_id), fallback_srh) {
+            (Some(srh), _) => srh,
+            (None, Some(fallback_srh)) => {
+                self.logger.log_warn(&format!(
+                    "[{tag}] - Failed to get routing path, using reversed sender path"
+                ));
+                fallback_srh.clone()
+            }
+            (None, None) => return Err(ServerError::NoRoute(client_id)),
+        };
+
+        // Disassemble the message into Packets
+        let packets = match self.packet_forge.disassemble(message.clone(), &srh) {
+            Ok(packets) => packets,
+            Err(msg) => {
+                self.logger
+                    .log_debug(&format!("[VERBOSE] {message:?}\n Error: {msg}"));
+                return Err(ServerError::Disassemble(msg));
+            }
+        };
+
+        let next_hop = srh.hops[srh.hop_index];
+        self.send_save_packets(&packets, next_hop)?;
+
+        self.logger
+            .log_info(&format!("[{tag}] - Sent successfully!"));
+        Ok(())
+    }
+
     /// Sends a `DroneEvent` containing the `packet` that has been sent.
     pub(crate) fn event_dispatcher(&self, packet: &Packet, packet_str: &str) {
         if let Err(err) = sc_send_packet(
@@ -83,16 +166,18 @@ impl Server {
         &self,
         packets: &[Packet],
         next_hop: NodeId,
-    ) -> Result<(), String> {
+    ) -> Result<(), ServerError> {
         // Get the sender channel for the next hop and forward
         let sender = get_sender(next_hop, &self.packet_send)?;
 
         for packet in packets {
             let packet_str = get_packet_type(&packet.pack_type).to_uppercase();
-            if let Err(err) = send_packet(&sender, packet) {
+            if let Err(err) = send_packet(&sender, packet, next_hop) {
                 // If Packet is ack, nack or flood response try controller shortcut
                 if packet_str == "ACK" || packet_str == "NACK" || packet_str == "FLOOD RESPONSE" {
-                    self.logger.log_warn(&format!("[{packet_str}] - Failed to forward packet to [DRONE-{next_hop}]. \n Error: {err} \n Trying to use SC shortcut..."));
+                    self.logger.log_warn(&format!(
+                        "[{packet_str}] - {err} \n Trying to use SC shortcut..."
+                    ));
                     // Send to SC
                     let res = sc_send_packet(
                         &self.controller_send,
@@ -100,9 +185,10 @@ impl Server {
                     );
 
                     if let Err(err) = res {
-                        self.logger.log_error(&format!("[{packet_str}] - {err}"));
-                        return Err(format!(
-                    "[{packet_str}] - Unable to forward packet to neither next hop nor SC. \n {packet}"));
+                        self.logger.log_error(&format!(
+                            "[{packet_str}] - Unable to forward packet to neither next hop nor SC. \n {packet}"
+                        ));
+                        return Err(err);
                     }
 
                     self.logger
@@ -111,9 +197,7 @@ impl Server {
                     return Ok(());
                 }
 
-                return Err(format!(
-                    "Failed to send packet to [DRONE-{next_hop}].\n {packet} \n Error: {err}"
-                ));
+                return Err(err);
             }
 
             self.logger
@@ -124,19 +208,44 @@ impl Server {
     }
 
     /// This function has two purposes:
-    /// - send the fragments contained within each Packet to their destination
-    /// - save each packet into `packet_history`
+    /// - send the fragments contained within each Packet to their destination.
+    ///   They are queued in the `SendWindow` of the destination client, shared with its other sessions,
+    ///   and only the ones that fit in the window are sent immediately, the others are released as `Ack`s arrive
+    /// - save each sent packet into `packet_history`
+    ///
+    /// All the packets must belong to the same session.
     /// ### Error
-    /// If the channel of the `next_hop` is not found returns Err(String).
+    /// If the channel of the `next_hop` is not found returns `ServerError::NoNeighbour`.
+    /// If the session does not fit the history budget nothing is sent and `ServerError::HistoryBudgetExceeded` is returned.
     pub(crate) fn send_save_packets(
         &mut self,
         packets: &[Packet],
         next_hop: NodeId,
-    ) -> Result<(), String> {
-        self.send_packets_vec(packets, next_hop)?;
+    ) -> Result<(), ServerError> {
+        let Some(first) = packets.first() else {
+            return Ok(());
+        };
+        let session_id = first.session_id;
+        let Some(destination) = Self::destination(first) else {
+            return Err(ServerError::NoRoute(next_hop));
+        };
+
+        if !self.sent_fragments_history.fits(packets) {
+            return Err(ServerError::HistoryBudgetExceeded(session_id));
+        }
 
-        self.insert_packet_history(packets);
+        // Windows are kept once created, there is at most one per node
+        let window = self.send_windows.entry(destination).or_default();
+        window.enqueue(packets, next_hop);
+        let ready = window.release();
+        let (queued, in_flight) = (window.queued(), window.in_flight());
 
-        Ok(())
+        if queued > 0 {
+            self.logger.log_debug(&format!(
+                "[FLOW CONTROL] Session {session_id}: {queued} fragments queued towards [CLIENT-{destination}], {in_flight} in flight"
+            ));
+        }
+
+        self.send_released(ready)
     }
 }
@@ -0,0 +1,51 @@
+use super::Server;
+use crate::server::scheduler::Task;
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default time after which a quarantined file is dropped
+pub(crate) const DEFAULT_QUARANTINE_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// How the server treats a submitted file whose `FileHash` does not match its metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValidationPolicy {
+    /// Refuse the file
+    #[default]
+    Reject,
+    /// Keep the file aside without listing it. It leaves the quarantine when the client submits it again with the right
+    /// `FileHash`, deletes it or unsubscribes, or once it has been kept for longer than the quarantine expiry.
+    Quarantine,
+    /// Process the file under the received `FileHash` and warn the client
+    AcceptWithWarning,
+}
+
+impl Server {
+    /// Drop the quarantined files that the clients have not fixed in time.
+    pub(crate) fn expire_quarantine(&mut self) {
+        let now = SystemTime::now();
+        let deadline = now
+            .checked_sub(self.quarantine_expiry)
+            .unwrap_or(UNIX_EPOCH);
+
+        let (expired, oldest) = match self.database.expire_quarantine(deadline) {
+            Ok(res) => res,
+            Err(err) => {
+                self.logger.log_error(&format!("[QUARANTINE] - {err}"));
+                return;
+            }
+        };
+        for (client_id, file_metadata) in expired {
+            self.logger.log_info(&format!(
+                "[QUARANTINE] - Dropped expired file of [CLIENT-{client_id}] [ {file_metadata:?} ]"
+            ));
+        }
+
+        if let Some(oldest) = oldest {
+            let wait = (oldest + self.quarantine_expiry)
+                .duration_since(now)
+                .unwrap_or_default();
+            self.scheduler
+                .schedule(Task::QuarantineExpiry, Instant::now() + wait);
+        }
+    }
+}
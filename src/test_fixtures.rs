@@ -0,0 +1,56 @@
+use packet_forge::{FileHash, SessionIdT, SongMetaData, VideoMetaData};
+use wg_internal::network::SourceRoutingHeader;
+use wg_internal::packet::{Fragment, Packet, FRAGMENT_DSIZE};
+
+/* UNIT TEST FIXTURES */
+
+/// Song of a client, two songs with the same `title` have the same digest whatever their id
+pub(crate) fn song(id: FileHash, title: &str) -> SongMetaData {
+    SongMetaData {
+        id,
+        title: title.to_string(),
+        artist: "artist".to_string(),
+        album: "album".to_string(),
+        duration: 60,
+        image_url: String::new(),
+        is_local: false,
+    }
+}
+
+/// Video of a client, two videos with the same `title` have the same digest whatever their id
+pub(crate) fn video(id: FileHash, title: &str) -> VideoMetaData {
+    VideoMetaData {
+        id,
+        title: title.to_string(),
+        description: "description".to_string(),
+        duration: 60,
+        mime_type: "video/mp4".to_string(),
+        created_at: "12:00".to_string(),
+    }
+}
+
+/// Empty fragment `fragment_index` of a message split in `total_n_fragments`
+pub(crate) fn fragment(fragment_index: u64, total_n_fragments: u64) -> Fragment {
+    Fragment {
+        fragment_index,
+        total_n_fragments,
+        length: 0,
+        data: [0; FRAGMENT_DSIZE],
+    }
+}
+
+/// Fragment packet of `session_id` sent by node 1 to node 3 through node 2
+pub(crate) fn fragment_packet(
+    session_id: SessionIdT,
+    fragment_index: u64,
+    total_n_fragments: u64,
+) -> Packet {
+    Packet::new_fragment(
+        SourceRoutingHeader {
+            hop_index: 1,
+            hops: vec![1, 2, 3],
+        },
+        session_id,
+        fragment(fragment_index, total_n_fragments),
+    )
+}
@@ -20,6 +20,24 @@ pub fn check_packet_dest(
     }
 }
 
+/// Check that `routing_header` can be followed back to its source: at least a source and a destination,
+/// with a hop index inside the hops.
+/// ### Error
+/// Returns the reason why the header is malformed.
+pub fn check_routing_header(routing_header: &SourceRoutingHeader) -> Result<(), String> {
+    let n_hops = routing_header.hops.len();
+    if n_hops < 2 {
+        return Err(format!("routing header with {n_hops} hops"));
+    }
+    if routing_header.hop_index >= n_hops {
+        return Err(format!(
+            "hop index {} out of {n_hops} hops",
+            routing_header.hop_index
+        ));
+    }
+    Ok(())
+}
+
 /// Returns the `PacketType` formatted as as `String`
 pub fn get_packet_type(pt: &PacketType) -> String {
     match pt {
@@ -30,3 +48,25 @@ pub fn get_packet_type(pt: &PacketType) -> String {
         PacketType::MsgFragment(_) => "Fragment".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(hop_index: usize, hops: Vec<NodeId>) -> SourceRoutingHeader {
+        SourceRoutingHeader { hop_index, hops }
+    }
+
+    #[test]
+    fn accepts_well_formed_headers() {
+        assert!(check_routing_header(&header(1, vec![1, 2])).is_ok());
+        assert!(check_routing_header(&header(2, vec![1, 2, 3])).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(check_routing_header(&header(0, vec![])).is_err());
+        assert!(check_routing_header(&header(0, vec![1])).is_err());
+        assert!(check_routing_header(&header(3, vec![1, 2, 3])).is_err());
+    }
+}